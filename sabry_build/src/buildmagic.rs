@@ -1,22 +1,46 @@
 use std::{
-    collections::HashSet,
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     convert::Infallible,
+    env,
+    fmt::Debug,
     fs::{self, OpenOptions},
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
     vec,
 };
 
 use sabry_intrnl::{
-    compiler::{CompilerAdapter, SabryCompilerError},
-    config::{manifest::ManifestError, BehavHashCollision, BehavSassModCollision, SabryConfig},
-    scoper::{hash::ScopeHash, ArbitraryScope, ScopeError},
+    compiler::{
+        sourcemap::{self, GluedSourceMaps, StyleOrigin},
+        CompilerAdapter, SabryCompilerError,
+    },
+    config::{
+        manifest::{
+            self, ManifestError, HASH_REGISTRY_ENV, MANGLE_TABLE_ENV, PROFILE_ENV,
+            SCOPE_MODULES_ENV,
+        },
+        BehavHashCollision, BehavSassModCollision, BehavScopeStage, BehavStyleDiscovery,
+        SabryConfig,
+    },
+    diagnostic::{Diagnostic, Severity},
+    scoper::{
+        hash::ScopeHash, mangle::MangleTable, origin::ScopeOrigin, registry::HashRegistry,
+        ArbitraryScope, ScopeError, LEPTY_SCOPING,
+    },
+    syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode},
 };
 use sabry_procmacro_impl::impls::{styly, ArbitraryStyleSyntax};
 use walkdir::WalkDir;
 
-use crate::filevisit::{self, FileVisitError};
+use crate::{
+    cache::{self, BuildCache},
+    cfg::CfgSet,
+    filevisit::{self, FileVisitError, ModuleFile, StylyVisitor},
+    pool,
+};
 
 type ModuleName = String;
 type ModuleCode = String;
@@ -68,6 +92,11 @@ pub fn buildy(inline_side_modules: impl IntoIterator<Item = StyleModule>) -> Bui
 pub struct SabryBuilder {
     config: SabryConfig,
     css_compiler: CompilerAdapter,
+    cache: BuildCache,
+    rerun_directives: bool,
+    jobs: usize,
+    /// whether scopes are qualified with module path, see [ScopeOrigin::macros_know_file]
+    scope_modules: bool,
     state: SabryBuildState,
 }
 
@@ -83,13 +112,57 @@ impl SabryBuilder {
     /// ```
     pub fn new(config: SabryConfig) -> Self {
         let css_compiler = CompilerAdapter::new(config.clone());
+        let cache = BuildCache::new(format!("{}/.cache", config.sass.intermediate_dir));
         Self {
             config,
             css_compiler,
+            cache,
+            rerun_directives: true,
+            jobs: env::var("NUM_JOBS")
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(1),
+            scope_modules: ScopeOrigin::macros_know_file(),
             state: SabryBuildState::default(),
         }
     }
 
+    /// Whether [Self::build] prints `cargo:rerun-if-changed` directives for every file it reads.
+    ///
+    /// On by default. Turn it off when the builder is not run from the build script,
+    /// or the build script takes care of the directives by itself
+    pub fn rerun_directives(mut self, emit: bool) -> Self {
+        self.rerun_directives = emit;
+        self
+    }
+
+    /// Number of threads [Self::compile_everything] compiles scopes with.
+    ///
+    /// Defaults to `NUM_JOBS` cargo sets for build scripts, and to a single thread outside of those.
+    /// The output is the same regardless
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Every file the builder has read so far: manifest, preludes, modules, scanned sources and style files
+    pub fn touched_files(&self) -> impl Iterator<Item = &Path> {
+        self.state.touched_files.iter().map(|p| p.as_path())
+    }
+
+    /// Tell cargo to rerun the build script only when something the builder has read is changed
+    pub fn emit_rerun_directives(&self) {
+        for path in self.touched_files() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        // cargo scans directories recursively, so the scanroot is only told when every file in it matters.
+        // In the module tree, new files come with `mod` declarations in files already told
+        if let BehavStyleDiscovery::Walk = self.config.sass.discovery {
+            println!("cargo:rerun-if-changed={}", self.config.sass.scanroot);
+        }
+        println!("cargo:rerun-if-env-changed={PROFILE_ENV}");
+    }
+
     /// Default building workflow implementation
     pub fn build(
         &mut self,
@@ -97,6 +170,20 @@ impl SabryBuilder {
     ) -> BuilderResult {
         println!("🧙: This is probably the stderr. Something went wrong:");
 
+        // so the styly! macros of this crate pick the same profile settings
+        if let Some(profile) = manifest::active_profile() {
+            println!("cargo:rustc-env={PROFILE_ENV}={profile}");
+        }
+        // so the styly! macros qualify scopes the same way, whether they know their file or not
+        println!(
+            "cargo:rustc-env={SCOPE_MODULES_ENV}={}",
+            u8::from(self.scope_modules)
+        );
+
+        self.state
+            .touched_files
+            .insert(manifest::crate_path(manifest::MANIFEST));
+
         println!("🧙 loading preludes");
         self.load_preludes()?;
 
@@ -108,12 +195,26 @@ impl SabryBuilder {
         println!("🧙 loading this crate");
         self.load_styles_from_this_crate()?;
 
+        println!("🧙 registering hashes");
+        self.register_hashes()?;
+
+        if self.config.hash.mangle {
+            println!("🧙 mangling names");
+            self.mangle_everything()?;
+        }
+
         println!("🧙 compiling CSS");
         self.compile_everything()?;
 
         println!("🧙 writing an output");
         self.generate_output()?;
 
+        self.cache.prune()?;
+
+        if self.rerun_directives {
+            self.emit_rerun_directives();
+        }
+
         Ok(())
     }
 
@@ -130,10 +231,14 @@ impl SabryBuilder {
         if let Some(scope_dir) = &self.config.css.scopes {
             println!("🧙 writing CSS files for each of loaded scopes into {scope_dir}");
 
-            fs::create_dir_all(scope_dir)?;
             for (scope, code) in &self.state.loaded_css_modules {
                 let scope_path = format!("{}/{}.css", scope_dir, scope);
-                fs::write(scope_path, code)?;
+                match self.state.loaded_css_maps.get(scope) {
+                    Some(map) => Self::write_mapped(&scope_path, code, map)?,
+                    None => {
+                        cache::write_if_changed(Path::new(&scope_path), code)?;
+                    }
+                }
             }
         }
 
@@ -142,31 +247,244 @@ impl SabryBuilder {
 
             let path = PathBuf::from_str(bundle_file)?;
 
-            if let Some(dir) = path.parent() {
-                fs::create_dir_all(dir)?;
-            }
-
-            // remove the file to write new fresh tasty CSS
-            let _ = fs::remove_file(&path);
-            // and then reopen it
-            let mut file = OpenOptions::new()
-                .create_new(true)
-                .append(true)
-                .open(path)?;
-
             // merged bundle CSS does require another lightningcss pass
             let mut buffer = String::new();
-            for (_, code) in &self.state.loaded_css_modules {
+            let mut maps = String::new();
+            let mut glued = GluedSourceMaps::default();
+            for (scope, code) in &self.state.loaded_css_modules {
+                if self.config.css.sourcemap {
+                    let map = self.state.loaded_css_maps.get(scope).map(|m| m.as_str());
+                    glued
+                        .push(sourcemap::end_of(&buffer), map)
+                        .map_err(SabryCompilerError::from)?;
+                    maps.push_str(map.unwrap_or_default());
+                }
                 buffer.push_str(code);
             }
-            buffer = self.css_compiler.lightningcss(&buffer)?;
 
-            write!(file, "{buffer}")?;
+            let key = BuildCache::key([
+                b"bundle".as_slice(),
+                self.inputs_key().as_bytes(),
+                buffer.as_bytes(),
+                maps.as_bytes(),
+            ]);
+
+            if self.config.css.sourcemap {
+                let map_key = format!("{key}.map");
+                let (css, map) = match (self.cache.get(&key), self.cache.get(&map_key)) {
+                    (Some(css), Some(map)) => (css, map),
+                    _ => {
+                        let (css, map) = self
+                            .css_compiler
+                            .lightningcss_mapped(&buffer, |l, c| glued.resolve(l, c))?;
+                        self.cache.put(&key, &css)?;
+                        self.cache.put(&map_key, &map)?;
+                        (css, map)
+                    }
+                };
+
+                Self::write_mapped(bundle_file, &css, &map)?;
+            } else {
+                let buffer = match self.cache.get(&key) {
+                    Some(css) => css,
+                    None => {
+                        let css = self.css_compiler.lightningcss(&buffer)?;
+                        self.cache.put(&key, &css)?;
+                        css
+                    }
+                };
+
+                cache::write_if_changed(&path, &buffer)?;
+            }
         }
 
         Ok(())
     }
 
+    /// Write CSS file along with its source map file next to it
+    fn write_mapped(path: &str, css: &str, map: &str) -> BuilderResult {
+        let map_path = format!("{path}.map");
+        let map = sourcemap::with_source_root(map, &map_path).map_err(SabryCompilerError::from)?;
+
+        cache::write_if_changed(Path::new(&map_path), &map)?;
+        cache::write_if_changed(
+            Path::new(path),
+            &sourcemap::with_map_comment(css, &map_path),
+        )?;
+
+        Ok(())
+    }
+
+    /// Hash all the loaded styles, resolve hash collisions as configured,
+    /// and keep the hash registry for [Self::compile_everything] to use.
+    ///
+    /// The registry is written into `OUT_DIR` for `styly!` macros to read, if run from the build script.
+    ///
+    /// Scopes are hashed in the order of their qualified names, so the resolution is deterministic.
+    /// Collisions are ignored by default, and the registry is neither written nor read then
+    pub fn register_hashes(&mut self) -> BuilderResult {
+        if matches!(self.config.hash.collision, BehavHashCollision::Ignore) {
+            // so other crates do not check theirs against the stale one
+            if let Some((dir, krate)) = Self::workspace_hashes() {
+                Self::remove_workspace_hashes(&dir, &krate)?;
+            }
+            return Ok(());
+        }
+
+        let mut stylyses = self.state.loaded_stylyses.iter().collect::<Vec<_>>();
+        stylyses.sort_by_key(|s| s.origin.qualify(&s.scope));
+
+        let mut registry = HashRegistry::default();
+        for styly in stylyses {
+            let scopable = self.scopable(styly)?;
+            let scope = Self::scope_of(styly, &scopable)?.with_origin(styly.origin.clone());
+            let scope_name = scope.qualified_name();
+
+            let mut salt = 0;
+            let mut hash = ScopeHash::salted(&scope, &self.config.hash, salt);
+            while let Some(other) = registry.colliding(&scope_name, hash.as_str()) {
+                match self.config.hash.collision {
+                    BehavHashCollision::Ignore => break,
+                    BehavHashCollision::Error => {
+                        return Err(SabryBuildError::HashCollision {
+                            scope: scope_name,
+                            other: other.to_string(),
+                        })
+                    }
+                    BehavHashCollision::Rehash => {
+                        salt += 1;
+                        hash = ScopeHash::salted(&scope, &self.config.hash, salt);
+                    }
+                }
+            }
+
+            registry.insert(scope_name, salt, hash.as_str().to_string());
+        }
+
+        if let Ok(out) = env::var("OUT_DIR") {
+            let path = Path::new(&out).join("sabry").join("hashes");
+            registry.write(&path)?;
+            println!("cargo:rustc-env={HASH_REGISTRY_ENV}={}", path.display());
+        }
+        self.check_workspace_hashes(&registry)?;
+        self.config.hash.registry = Some(Arc::new(registry));
+
+        Ok(())
+    }
+
+    /// Share the hash registry with other crates built into the same target directory,
+    /// and check their scopes do not collide with ones of this crate.
+    ///
+    /// Crates are built in no particular order, so those collisions can not be resolved deterministically,
+    /// and are only reported with `Rehash` strategy.
+    ///
+    /// Every registry is put along with the root of its crate. Registries of crates,
+    /// which are gone or renamed since, are stale, and are removed instead of being checked
+    fn check_workspace_hashes(&self, registry: &HashRegistry) -> BuilderResult {
+        let (Some((dir, krate)), Ok(root)) = (
+            Self::workspace_hashes(),
+            env::var(manifest::MANIFEST_DIR_ENV),
+        ) else {
+            return Ok(());
+        };
+
+        registry.write(&dir.join(&krate))?;
+        fs::write(dir.join(format!("{krate}.root")), root)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(other) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            if other == krate || other.ends_with(".root") {
+                continue;
+            }
+
+            let other_root = fs::read_to_string(dir.join(format!("{other}.root"))).ok();
+            let stale = other_root
+                .and_then(|r| manifest::package_name(Path::new(&r)))
+                .map_or(true, |name| name != other);
+            if stale {
+                Self::remove_workspace_hashes(&dir, &other)?;
+                continue;
+            }
+
+            let others = HashRegistry::read(&path)?;
+            for (scope, other) in registry.collisions_with(&others) {
+                match self.config.hash.collision {
+                    BehavHashCollision::Ignore => {}
+                    BehavHashCollision::Error => {
+                        return Err(SabryBuildError::HashCollision {
+                            scope: scope.to_string(),
+                            other: other.to_string(),
+                        })
+                    }
+                    BehavHashCollision::Rehash => println!(
+                        "cargo:warning=sabry: scope '{scope}' has the same hash as '{other}' of another crate"
+                    ),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Directory the hash registries of all the crates built into the same target directory are shared in,
+    /// along with the name of this crate
+    fn workspace_hashes() -> Option<(PathBuf, String)> {
+        // OUT_DIR is {target}/{profile}/build/{package}/out
+        let dir = env::var("OUT_DIR").ok().and_then(|out| {
+            PathBuf::from(out)
+                .ancestors()
+                .find(|a| a.file_name().is_some_and(|n| n == "build"))
+                .and_then(|b| b.parent())
+                .map(|p| p.join(".sabry").join("hashes"))
+        })?;
+
+        Some((dir, env::var("CARGO_PKG_NAME").ok()?))
+    }
+
+    /// Remove the shared hash registry of the crate, if any
+    fn remove_workspace_hashes(dir: &Path, krate: &str) -> BuilderResult {
+        for path in [dir.join(krate), dir.join(format!("{krate}.root"))] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assign the shortest unique names to scoped identifiers of all the loaded styles,
+    /// for [Self::compile_everything] to use.
+    ///
+    /// The mangling table is written into `OUT_DIR` for `styly!` macros to read, if run from the build script
+    pub fn mangle_everything(&mut self) -> BuilderResult {
+        let mut keys = vec![];
+        for styly in &self.state.loaded_stylyses {
+            let scopable = self.scopable(styly)?;
+            let scope = Self::scope_of(styly, &scopable)?
+                .with_origin(styly.origin.clone())
+                .hashed(&self.config.hash)?;
+
+            keys.extend(scope.hashed_selectors.iter().filter_map(|hs| {
+                hs.sel
+                    .local()
+                    .map(|l| (scope.hash.as_str().to_string(), l.to_string()))
+            }));
+        }
+
+        let table = MangleTable::assign(keys);
+        if let Ok(out) = env::var("OUT_DIR") {
+            let path = Path::new(&out).join("sabry").join("mangle");
+            table.write(&path)?;
+            println!("cargo:rustc-env={MANGLE_TABLE_ENV}={}", path.display());
+        }
+        self.config.hash.mangle_names = Some(Arc::new(table));
+
+        Ok(())
+    }
+
     /// Compile all the loaded styles, SASS/SCSS/CSS, without actually writing them.
     pub fn compile_everything(&mut self) -> BuilderResult {
         // warn on empty known_side_modules and loaded_stylyses
@@ -177,33 +495,81 @@ impl SabryBuilder {
             println!("🧙 sabry didn't load any usable styles");
         }
 
-        // compile styly! macro parsed styles
+        // cached scopes are taken right away, the rest is compiled in parallel
+        let inputs_key = self.inputs_key();
+        let mut cached = vec![];
+        let mut jobs = vec![];
         for styly in &self.state.loaded_stylyses {
-            let scope = ArbitraryScope::from_source(
-                styly.syntax.into(),
-                styly.scope.clone(),
-                styly.code.code(),
-            )?
-            .hashed(&self.config.hash)?;
+            let source = self
+                .config
+                .css
+                .sourcemap
+                .then(|| Self::style_origin(styly))
+                .flatten();
+            let key = BuildCache::key([
+                inputs_key.as_bytes(),
+                styly.origin.qualify(&styly.scope).as_bytes(),
+                format!("{:?}", styly.syntax).as_bytes(),
+                styly.code.code().as_bytes(),
+                format!("{source:?}").as_bytes(),
+            ]);
+
+            // scopes with source map are cached only along with it
+            let hit = match (self.cache.get(&key), &source) {
+                (Some(css), Some(_)) => self
+                    .cache
+                    .get(&format!("{key}.map"))
+                    .map(|map| (css, Some(map))),
+                (Some(css), None) => Some((css, None)),
+                (None, _) => None,
+            };
+            match hit {
+                Some(css) => cached.push(Some((styly.origin.chunk(&styly.scope), css))),
+                None => {
+                    cached.push(None);
+                    jobs.push(ScopeJob {
+                        key,
+                        scope: styly.scope.to_string(),
+                        origin: styly.origin.clone(),
+                        source,
+                        syntax: styly.syntax,
+                        code: styly.code.code().to_string(),
+                    });
+                }
+            }
+        }
+
+        let compiler = self.scope_compiler();
+        let mut compiled = pool::run(&jobs, self.jobs, |job| compiler.compile(job)).into_iter();
+
+        // results are gone through in the loading order, so errors are the same as of serial compilation
+        for module in cached {
+            // collisions are checked with the hash registry anyway, so cached scopes are not checked
+            if let Some((chunk, (css, map))) = module {
+                self.load_css_module(chunk, css, map);
+                continue;
+            }
+
+            let scope = compiled
+                .next()
+                .expect("BUG: every uncached scope has a compilation job")?;
 
             match self.config.hash.collision {
-                BehavHashCollision::Ignore => {}
+                // hashes are already salted as registered
+                BehavHashCollision::Ignore | BehavHashCollision::Rehash => {}
                 BehavHashCollision::Error => {
-                    if self.state.known_scope_hashes.contains(&scope.hash) {
+                    if let Some(other) = self.state.known_scope_hashes.get(&scope.hash) {
                         return Err(SabryBuildError::HashCollision {
-                            scope: scope.original_scope.name.to_string(),
+                            scope: scope.name,
+                            other: other.clone(),
                         });
                     }
-                    self.state.known_scope_hashes.insert(scope.hash.clone());
+                    self.state.known_scope_hashes.insert(scope.hash, scope.name);
                 }
             }
 
-            let css = self
-                .css_compiler
-                .compile_module(scope.original_scope.adapter().syntax, &scope.hashed_code)?;
-            self.state
-                .loaded_css_modules
-                .push((scope.original_scope.name.to_string(), css));
+            let (css, map) = scope.css?;
+            self.load_css_module(scope.chunk, css, map);
         }
 
         // compile sass preludes into the CSS prelude
@@ -217,29 +583,150 @@ impl SabryBuilder {
         Ok(())
     }
 
+    fn load_css_module(&mut self, name: ModuleName, css: ModuleCode, map: Option<String>) {
+        if let Some(map) = map {
+            self.state.loaded_css_maps.insert(name.clone(), map);
+        }
+        self.state.loaded_css_modules.push((name, css));
+    }
+
+    /// Where the code of the `styly!` macro starts: the style file, or the literal in the rust file.
+    ///
+    /// Unknown, unless the macro is found by visiting the file
+    fn style_origin(styly: &styly::MacroSyntax) -> Option<StyleOrigin> {
+        let relative = |p: &Path| {
+            manifest::crate_relative(p)
+                .to_string_lossy()
+                .replace('\\', "/")
+        };
+
+        match styly.code.path() {
+            Some(path) => Some(StyleOrigin {
+                file: relative(path),
+                line: 0,
+                column: 0,
+            }),
+            None => {
+                // span lines are one-based, source map ones are not
+                let start = styly.code.span().start();
+                styly.file.as_deref().map(|file| StyleOrigin {
+                    file: relative(file),
+                    line: start.line.saturating_sub(1) as u32,
+                    column: start.column as u32,
+                })
+            }
+        }
+    }
+
+    /// Code of the `styly!` macro to be scoped, see [ScopeCompiler::scopable]
+    fn scopable<'c>(
+        &self,
+        styly: &'c styly::MacroSyntax,
+    ) -> Result<StyleCode<'c>, SabryBuildError> {
+        self.scope_compiler()
+            .scopable(styly.syntax, styly.code.code())
+            .map(|(syntax, code)| StyleCode::new(syntax, code))
+            .map_err(|e| match &e {
+                SabryBuildError::CssCompile(ce) if ce.location().is_some() => {
+                    Self::style_error(styly, Diagnostic::from(ce))
+                }
+                _ => e,
+            })
+    }
+
+    /// Scope of the `styly!` macro code, made [Self::scopable]
+    fn scope_of<'c>(
+        styly: &styly::MacroSyntax,
+        code: &'c StyleCode<'c>,
+    ) -> Result<ArbitraryScope<'c>, SabryBuildError> {
+        ArbitraryScope::from_source(styly.scope.clone(), code).map_err(|e| match code.code {
+            Cow::Borrowed(_) => Self::style_error(styly, Diagnostic::from(&e)),
+            // compiled code has nothing to do with the one written
+            Cow::Owned(_) => SabryBuildError::from(e),
+        })
+    }
+
+    /// Error about the code of the `styly!` macro, pointing at the code as it is written
+    fn style_error(styly: &styly::MacroSyntax, diagnostic: Diagnostic) -> SabryBuildError {
+        let diagnostic = styly.code.diagnose(diagnostic, styly.file.as_deref());
+        SabryBuildError::Style(Box::new(diagnostic))
+    }
+
+    fn scope_compiler(&self) -> ScopeCompiler<'_> {
+        ScopeCompiler {
+            config: &self.config,
+            css_compiler: &self.css_compiler,
+            cache: &self.cache,
+            inputs_key: self.inputs_key(),
+        }
+    }
+
+    /// Cache key part for whatever every compilation depends on:
+    /// relevant configuration, the scoping flavour and the loaded side modules.
+    /// Hash registry and mangling table are a part of the hash config
+    fn inputs_key(&self) -> String {
+        let config = format!(
+            "{:?}{:?}{:?}{:?}{LEPTY_SCOPING}",
+            self.config.css.minify, self.config.sass, self.config.hash, self.config.lightningcss
+        );
+
+        BuildCache::key([
+            config.as_bytes(),
+            self.state.side_modules_digest.finalize().as_bytes(),
+        ])
+    }
+
     /// Visit all the source files in the current crate and look for code that may affect building process:
     ///
     /// - `styly!` macro calls
     ///
-    /// Only files with an **.rs** extension are visited.
+    /// Files are found as configured with `sass.discovery`: every file with an **.rs** extension under the scanroot,
+    /// or the files of the module tree, with respect to `#[cfg]` attributes.
+    ///
+    /// All the gathered info is loaded into state.
     ///
-    /// All the gathered info is loaded into state
+    /// The whole crate is scanned anyway: files, which can not be read or parsed, are reported as cargo warnings,
+    /// and all the invalid `styly!` macros are reported at once with [SabryBuildError::InvalidStylys]
     pub fn load_styles_from_this_crate(&mut self) -> BuilderResult {
         println!("🧙 scanning the crate");
 
+        let mut problems = vec![];
+        match self.config.sass.discovery {
+            BehavStyleDiscovery::Walk => self.walk_crate(&mut problems),
+            BehavStyleDiscovery::Modules => self.follow_modules(&mut problems),
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(SabryBuildError::InvalidStylys(problems)),
+        }
+    }
+
+    /// Visit every rust file under the scanroot
+    fn walk_crate(&mut self, problems: &mut Vec<Diagnostic>) {
         let root = WalkDir::new(&self.config.sass.scanroot);
+        let wrappers = self.config.sass.styly_wrappers.clone().unwrap_or_default();
+        let wrappers = wrappers.as_slice();
 
         for entry in root {
-            let entry = entry?;
-            let metadata = entry.metadata()?;
+            let walked = entry.and_then(|e| e.metadata().map(|m| (e, m)));
+            let (entry, metadata) = match walked {
+                Ok(em) => em,
+                Err(e) => {
+                    Self::warn(
+                        &Diagnostic::warning(&e).context("Could not walk through the crate"),
+                    );
+                    continue;
+                }
+            };
 
             if metadata.is_file() {
                 let entry_path = entry.path();
                 let ext = entry_path.extension().unwrap_or_default();
                 if ext == "rs" {
                     println!(".. reading {entry_path:?}");
-                    let visitor = filevisit::visit_file(entry_path)?;
-                    self.state.loaded_stylyses.extend(visitor.found_stylys);
+                    let visited = filevisit::visit_file(entry_path, wrappers);
+                    self.load_visited(entry_path, visited, problems);
                 }
             } else if metadata.is_symlink() {
                 println!(
@@ -248,8 +735,100 @@ impl SabryBuilder {
                 )
             }
         }
+    }
 
-        Ok(())
+    /// Visit the files of the module tree, starting from `lib.rs` and `main.rs` of the scanroot.
+    ///
+    /// Modules and items disabled with `#[cfg]` are skipped, as cargo tells features and options to the build script
+    fn follow_modules(&mut self, problems: &mut Vec<Diagnostic>) {
+        let cfg = CfgSet::from_env();
+        let root = Path::new(&self.config.sass.scanroot);
+        let wrappers = self.config.sass.styly_wrappers.clone().unwrap_or_default();
+        let wrappers = wrappers.as_slice();
+
+        let mut queue = ["lib.rs", "main.rs"]
+            .map(|f| root.join(f))
+            .into_iter()
+            .filter(|p| p.is_file())
+            .map(|path| ModuleFile { path, mod_rs: true })
+            .collect::<VecDeque<_>>();
+        if queue.is_empty() {
+            Self::warn(&Diagnostic::warning(format!(
+                "Neither lib.rs nor main.rs is found in '{}', so no styles are found",
+                root.display()
+            )));
+        }
+
+        let mut visited_files = HashSet::new();
+        while let Some(module) = queue.pop_front() {
+            // the same file may be declared as different modules with `#[path]`
+            let canonical = module.path.canonicalize().unwrap_or(module.path.clone());
+            if !visited_files.insert(canonical) {
+                continue;
+            }
+
+            println!(".. reading {:?}", module.path);
+            let visited = filevisit::visit_module(&module, &cfg, wrappers);
+            queue.extend(self.load_visited(&module.path, visited, problems));
+        }
+    }
+
+    /// Load whatever is found in the file, reporting its problems.
+    ///
+    /// Returns the files of modules the file declares, if any
+    fn load_visited(
+        &mut self,
+        path: &Path,
+        visited: Result<StylyVisitor, FileVisitError>,
+        problems: &mut Vec<Diagnostic>,
+    ) -> Vec<ModuleFile> {
+        // so the build reruns, once the file is fixed
+        self.state.touched_files.insert(path.to_owned());
+
+        let visitor = match visited {
+            Ok(v) => v,
+            Err(e) => {
+                Self::warn(&Diagnostic::from(&e).context(format!(
+                    "{} is skipped",
+                    manifest::crate_relative(path).display()
+                )));
+                return vec![];
+            }
+        };
+        for diagnostic in &visitor.diagnostics {
+            match diagnostic.severity {
+                Severity::Error => problems.push(diagnostic.clone()),
+                Severity::Warning => Self::warn(diagnostic),
+            }
+        }
+        self.state.touched_files.extend(
+            visitor
+                .found_stylys
+                .iter()
+                .filter_map(|s| s.code.path().map(|p| p.to_owned())),
+        );
+        self.state
+            .loaded_stylyses
+            .extend(visitor.found_stylys.into_iter().map(|mut styly| {
+                if !self.scope_modules {
+                    styly.origin = ScopeOrigin::new(None);
+                }
+                styly
+            }));
+
+        visitor.modules
+    }
+
+    /// Report the diagnostic as cargo warning
+    fn warn(diagnostic: &Diagnostic) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            ..diagnostic.clone()
+        };
+        // cargo takes a warning per line
+        for line in diagnostic.to_string().lines() {
+            println!("cargo:warning=sabry: {line}");
+        }
     }
 
     /// Load up configured preludes and side modules
@@ -264,7 +843,8 @@ impl SabryBuilder {
                     .ok_or(SabryBuildError::FileName())?
                     .to_string_lossy()
                     .to_string();
-                let code = fs::read_to_string(pre_path)?;
+                let code = fs::read_to_string(&pre_path)?;
+                self.state.touched_files.insert(pre_path);
                 modules.push((pre_name, code));
             }
         }
@@ -292,7 +872,8 @@ impl SabryBuilder {
                     }
                 };
 
-                let code = fs::read_to_string(pre_path)?;
+                let code = fs::read_to_string(&pre_path)?;
+                self.state.touched_files.insert(pre_path);
                 sass_preludes.push(SassPreludeModule { syntax, code });
             }
             self.state.sass_prelude.extend(sass_preludes);
@@ -302,6 +883,7 @@ impl SabryBuilder {
         if let Some(css_pre) = &self.config.css.prelude {
             for pre in css_pre {
                 let code = fs::read_to_string(pre)?;
+                self.state.touched_files.insert(PathBuf::from(pre));
                 self.state.css_prelude.push_str(&code);
             }
         }
@@ -349,6 +931,10 @@ impl SabryBuilder {
 
         write!(module_file, "\n{code}\n")?;
 
+        self.state
+            .side_modules_digest
+            .update(name.as_bytes())
+            .update(code.as_bytes());
         self.state.known_side_modules.insert(name);
 
         Ok(())
@@ -357,16 +943,24 @@ impl SabryBuilder {
 
 #[derive(Default)]
 pub struct SabryBuildState {
-    /// HashSet of scope hashes known by builder
+    /// HashMap of scope hashes known by builder, along with qualified scope names
     /// Used to determine hash collision
-    known_scope_hashes: HashSet<ScopeHash>,
+    known_scope_hashes: HashMap<ScopeHash, String>,
     /// HashSet of module names known by builder
     /// Used to determine module name collision
     known_side_modules: HashSet<ModuleName>,
+    /// Every file read by builder
+    /// Used to tell cargo when to rerun the build script
+    touched_files: BTreeSet<PathBuf>,
+    /// Digest of side modules names and code
+    /// Used to determine whether cached CSS is still valid
+    side_modules_digest: blake3::Hasher,
     /// styly! macro uses, parsed
     loaded_stylyses: Vec<styly::MacroSyntax>,
     /// CSS modules to form bundle/write separately
     loaded_css_modules: Vec<StyleModule>,
+    /// Source maps of [loaded_css_modules] by module name, if configured
+    loaded_css_maps: HashMap<ModuleName, String>,
     /// CSS prelude to write into bundle
     /// Lives separately from [loaded_css_modules] to avoid name collision
     css_prelude: String,
@@ -375,6 +969,112 @@ pub struct SabryBuildState {
     sass_prelude: Vec<SassPreludeModule>,
 }
 
+/// Parts of the builder, which scope compilation needs.
+///
+/// Unlike the builder, those may be shared between threads
+struct ScopeCompiler<'b> {
+    config: &'b SabryConfig,
+    css_compiler: &'b CompilerAdapter,
+    cache: &'b BuildCache,
+    inputs_key: String,
+}
+
+/// Compilation of a single `styly!` scope, taken apart from its [styly::MacroSyntax],
+/// as syntax tree types can not be sent to another thread
+struct ScopeJob {
+    /// cache key to put the compiled CSS with
+    key: String,
+    scope: String,
+    origin: ScopeOrigin,
+    /// where the code comes from, if the source map is to be made
+    source: Option<StyleOrigin>,
+    syntax: ArbitraryStyleSyntax,
+    code: String,
+}
+
+/// Output of [ScopeJob]. Compilation error is kept apart,
+/// so collisions are checked before it, as well as with serial compilation
+struct CompiledScope {
+    /// qualified scope name
+    name: String,
+    chunk: String,
+    hash: ScopeHash,
+    /// CSS along with its source map, if any
+    css: Result<(String, Option<String>), SabryBuildError>,
+}
+
+impl ScopeCompiler<'_> {
+    /// Code to be scoped, see [CompilerAdapter::scopable]
+    ///
+    /// SASS compilation of the *compiled* hash stage is cached
+    fn scopable<'c>(
+        &self,
+        syntax: ArbitraryStyleSyntax,
+        code: &'c str,
+    ) -> Result<(OneSyntaxToRuleThemAll, Cow<'c, str>), SabryBuildError> {
+        if matches!(self.config.hash.stage, BehavScopeStage::Source)
+            || matches!(syntax.into(), OneSyntaxToRuleThemAll::Css)
+        {
+            return Ok(self.css_compiler.scopable(syntax.into(), code)?);
+        }
+
+        let key = BuildCache::key([
+            b"scopable".as_slice(),
+            self.inputs_key.as_bytes(),
+            format!("{:?}", syntax).as_bytes(),
+            code.as_bytes(),
+        ]);
+        // compiled stage output is always SCSS
+        if let Some(code) = self.cache.get(&key) {
+            return Ok((OneSyntaxToRuleThemAll::Scss, Cow::Owned(code)));
+        }
+
+        let (syntax, code) = self.css_compiler.scopable(syntax.into(), code)?;
+        self.cache.put(&key, &code)?;
+
+        Ok((syntax, Cow::Owned(code.into_owned())))
+    }
+
+    /// Hash and compile the scope, putting CSS into the cache
+    fn compile(&self, job: &ScopeJob) -> Result<CompiledScope, SabryBuildError> {
+        let name = syn::parse_str(&job.scope)
+            .map_err(|e| SabryBuildError::Another(format!("Invalid scope name: {e}")))?;
+
+        let (syntax, code) = self.scopable(job.syntax, &job.code)?;
+        let code = StyleCode::new(syntax, code);
+        let scope = ArbitraryScope::from_source(name, &code)?
+            .with_origin(job.origin.clone())
+            .hashed(&self.config.hash)?;
+
+        let syntax = scope.original_scope.adapter().syntax;
+        let css = match &job.source {
+            Some(source) => self
+                .css_compiler
+                .compile_scoped_mapped(syntax, &scope.hashed_code, source)
+                .map(|(css, map)| (css, Some(map))),
+            None => self
+                .css_compiler
+                .compile_scoped(syntax, &scope.hashed_code)
+                .map(|css| (css, None)),
+        }
+        .map_err(SabryBuildError::from)
+        .and_then(|(css, map)| {
+            self.cache.put(&job.key, &css)?;
+            if let Some(map) = &map {
+                self.cache.put(&format!("{}.map", job.key), map)?;
+            }
+            Ok((css, map))
+        });
+
+        Ok(CompiledScope {
+            name: scope.original_scope.qualified_name(),
+            chunk: scope.original_scope.chunk_name(),
+            hash: scope.hash,
+            css,
+        })
+    }
+}
+
 /// Convenience struct for [SabryBuildState::sass_prelude]
 pub struct SassPreludeModule {
     /// syntax for the prelude
@@ -383,7 +1083,7 @@ pub struct SassPreludeModule {
     code: String,
 }
 
-#[derive(Debug, thiserror::Error)]
+#[derive(thiserror::Error)]
 pub enum SabryBuildError {
     #[error("Filesystem error")]
     Fs(#[from] io::Error),
@@ -399,8 +1099,15 @@ pub enum SabryBuildError {
     FileVisit(#[from] FileVisitError),
     #[error("Syntax of style can not be parsed")]
     SyntaxError(#[from] ScopeError),
-    #[error("Another scope has the same hash, and sabry is configured to raise an error. Try to adjust config, increase hash size, or change the style code")]
-    HashCollision { scope: ModuleName },
+    #[error("Style code is invalid")]
+    Style(Box<Diagnostic>),
+    #[error("{} invalid `styly!` macros found", .0.len())]
+    InvalidStylys(Vec<Diagnostic>),
+    #[error("Scope '{scope}' has the same hash as '{other}', and sabry is configured to raise an error. Try to adjust config, increase hash size, or change the style code")]
+    HashCollision {
+        scope: ModuleName,
+        other: ModuleName,
+    },
     #[error("Something's wrong with file path")]
     Path(#[from] Infallible),
     #[error("File name wasnt determined properly")]
@@ -412,3 +1119,92 @@ pub enum SabryBuildError {
     #[error("Another error")]
     Another(String),
 }
+
+impl From<&SabryBuildError> for Diagnostic {
+    fn from(err: &SabryBuildError) -> Self {
+        let diagnostic = match err {
+            SabryBuildError::FileVisit(e) => Diagnostic::from(e),
+            SabryBuildError::SyntaxError(e) => Diagnostic::from(e),
+            SabryBuildError::Style(d) => d.as_ref().clone(),
+            SabryBuildError::InvalidStylys(ds) => match ds.as_slice() {
+                [d] => d.clone(),
+                _ => return Diagnostic::error(err),
+            },
+            SabryBuildError::CssCompile(e) => Diagnostic::from(e),
+            SabryBuildError::Another(e) => return Diagnostic::error(e),
+            e => match std::error::Error::source(e) {
+                Some(source) => Diagnostic::error(source),
+                None => return Diagnostic::error(e),
+            },
+        };
+
+        diagnostic.context(err)
+    }
+}
+
+impl SabryBuildError {
+    /// All the diagnostics of the error: usually just one, but every invalid `styly!` for [Self::InvalidStylys]
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Self::InvalidStylys(ds) if ds.len() > 1 => ds.clone(),
+            e => vec![Diagnostic::from(e)],
+        }
+    }
+}
+
+impl Debug for SabryBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Self::InvalidStylys(ds) = self {
+            if ds.len() > 1 {
+                writeln!(f, "{self}:")?;
+            }
+        }
+        let diagnostics = self
+            .diagnostics()
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", diagnostics.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use sabry_intrnl::config::SabryConfig;
+
+    use super::{OneSyntaxToRuleThemAll, SabryBuilder};
+
+    #[test]
+    fn css_prelude_by_extension() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let prelude = dir.join("prelude.css");
+        fs::write(
+            &prelude,
+            "main {\n    margin: 0;\n    & > article {\n        padding: 1rem;\n    }\n}",
+        )
+        .unwrap();
+
+        let mut config = SabryConfig::default();
+        config.css.minify = false;
+        config.sass.intermediate_dir = dir.join("intermediate").to_string_lossy().into();
+        config.sass.prelude = Some(vec![prelude.to_string_lossy().into()]);
+        // no native nesting there, so it is lowered
+        config.lightningcss.targets.chrome = Some("100".into());
+
+        let mut builder = SabryBuilder::new(config);
+        builder.load_preludes().unwrap();
+        assert!(matches!(
+            builder.state.sass_prelude[0].syntax.into(),
+            OneSyntaxToRuleThemAll::Css
+        ));
+
+        builder.compile_everything().unwrap();
+        assert_eq!(
+            "main {\n  margin: 0;\n}\n\nmain > article {\n  padding: 1rem;\n}\n",
+            builder.state.css_prelude
+        );
+    }
+}
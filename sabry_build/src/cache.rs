@@ -0,0 +1,134 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Persistent build cache: compiled CSS by blake3 digest of whatever it was compiled from.
+///
+/// Lives in a directory, one file per entry. Entries not used during the build
+/// are removed with [BuildCache::prune]. May be shared between threads
+pub struct BuildCache {
+    dir: PathBuf,
+    used: Mutex<HashSet<String>>,
+}
+
+impl BuildCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            used: Mutex::default(),
+        }
+    }
+
+    /// Digest of the given parts to be used as the cache key
+    pub fn key<'p>(parts: impl IntoIterator<Item = &'p [u8]>) -> String {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            // length goes first, so parts can not bleed into each other
+            hasher.update(&part.len().to_le_bytes());
+            hasher.update(part);
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let content = fs::read_to_string(self.dir.join(key)).ok()?;
+        self.used().insert(key.to_string());
+        Some(content)
+    }
+
+    pub fn put(&self, key: &str, content: &str) -> io::Result<()> {
+        write_if_changed(&self.dir.join(key), content)?;
+        self.used().insert(key.to_string());
+        Ok(())
+    }
+
+    /// Remove all the entries, which were not used since the cache creation
+    pub fn prune(&self) -> io::Result<()> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(());
+        };
+
+        let used = self.used();
+        for entry in entries {
+            let path = entry?.path();
+            if !path
+                .file_name()
+                .is_some_and(|n| used.contains(n.to_string_lossy().as_ref()))
+            {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn used(&self) -> MutexGuard<'_, HashSet<String>> {
+        // the set stays consistent even if some thread has panicked
+        self.used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Write the file atomically, and only if its content differs.
+///
+/// Returns whether the file was written
+pub fn write_if_changed(path: &Path, content: &str) -> io::Result<bool> {
+    if fs::read_to_string(path).is_ok_and(|c| c == content) {
+        return Ok(false);
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // rename is atomic, unlike the write, so readers never see half of the file
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".sabry-tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{write_if_changed, BuildCache};
+
+    #[test]
+    fn cache_keeps_used_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("cache");
+
+        let (a, b) = (
+            BuildCache::key([b"a".as_slice(), b"bc"]),
+            BuildCache::key([b"ab".as_slice(), b"c"]),
+        );
+        assert_ne!(a, b);
+
+        let cache = BuildCache::new(&dir);
+        cache.put(&a, ".a{}").unwrap();
+        cache.put(&b, ".b{}").unwrap();
+
+        let cache = BuildCache::new(&dir);
+        assert_eq!(Some(".a{}".to_string()), cache.get(&a));
+        cache.prune().unwrap();
+
+        let cache = BuildCache::new(&dir);
+        assert_eq!(Some(".a{}".to_string()), cache.get(&a));
+        assert_eq!(None, cache.get(&b));
+    }
+
+    #[test]
+    fn write_only_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("style.css");
+
+        assert!(write_if_changed(&path, ".a{}").unwrap());
+        assert!(!write_if_changed(&path, ".a{}").unwrap());
+        assert!(write_if_changed(&path, ".b{}").unwrap());
+        assert_eq!(".b{}", fs::read_to_string(&path).unwrap());
+    }
+}
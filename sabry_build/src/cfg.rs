@@ -0,0 +1,135 @@
+use std::{collections::HashSet, env};
+
+use syn::{punctuated::Punctuated, Expr, Lit, Meta, Token};
+
+/// Configuration options the crate is compiled with, to evaluate `#[cfg]` attributes against.
+///
+/// Build scripts are told those by cargo: features with `CARGO_FEATURE_*` variables,
+/// and the rest with `CARGO_CFG_*` ones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    /// Enabled features, uppercased and with `-` replaced by `_`, as cargo tells them
+    features: HashSet<String>,
+    /// Set options, along with their value if any
+    options: HashSet<(String, Option<String>)>,
+}
+
+impl CfgSet {
+    /// Options of the crate being built, as cargo tells the build script
+    pub fn from_env() -> Self {
+        Self::from_vars(env::vars())
+    }
+
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut this = Self::default();
+        for (key, value) in vars {
+            if let Some(feature) = key.strip_prefix("CARGO_FEATURE_") {
+                this.features.insert(feature.to_string());
+            } else if let Some(option) = key.strip_prefix("CARGO_CFG_") {
+                let option = option.to_lowercase();
+                this.options.insert((option.clone(), None));
+                // multiple values, like of `target_feature`, are comma separated
+                for value in value.split(',').filter(|v| !v.is_empty()) {
+                    this.options
+                        .insert((option.clone(), Some(value.to_string())));
+                }
+            }
+        }
+        this
+    }
+
+    /// Whether all the `#[cfg]` attributes hold, so the item is compiled.
+    ///
+    /// Predicates sabry does not understand are considered to hold
+    pub fn enabled(&self, attrs: &[syn::Attribute]) -> bool {
+        attrs
+            .iter()
+            .filter(|a| a.path().is_ident("cfg"))
+            .all(|a| a.parse_args::<Meta>().map_or(true, |m| self.holds(&m)))
+    }
+
+    /// Evaluate the cfg predicate
+    pub fn holds(&self, predicate: &Meta) -> bool {
+        match predicate {
+            Meta::Path(p) => p
+                .get_ident()
+                .is_some_and(|i| self.has(&i.to_string(), None)),
+            Meta::NameValue(nv) => {
+                let value = match &nv.value {
+                    Expr::Lit(l) => match &l.lit {
+                        Lit::Str(s) => s.value(),
+                        _ => return true,
+                    },
+                    _ => return true,
+                };
+                nv.path
+                    .get_ident()
+                    .is_some_and(|i| self.has(&i.to_string(), Some(&value)))
+            }
+            Meta::List(l) => {
+                let Ok(nested) = l.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                else {
+                    return true;
+                };
+                let name = l.path.get_ident().map(|i| i.to_string());
+                match name.as_deref() {
+                    Some("all") => nested.iter().all(|m| self.holds(m)),
+                    Some("any") => nested.iter().any(|m| self.holds(m)),
+                    Some("not") => nested.len() != 1 || !self.holds(&nested[0]),
+                    _ => true,
+                }
+            }
+        }
+    }
+
+    fn has(&self, option: &str, value: Option<&str>) -> bool {
+        match (option, value) {
+            ("feature", Some(f)) => self.features.contains(&f.replace('-', "_").to_uppercase()),
+            (option, value) => self
+                .options
+                .contains(&(option.to_string(), value.map(|v| v.to_string()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use syn::{parse_quote, ItemMod};
+
+    use super::CfgSet;
+
+    #[test]
+    fn cfg_predicates() {
+        let cfg = CfgSet::from_vars(
+            [
+                ("CARGO_FEATURE_DARK_MODE", ""),
+                ("CARGO_CFG_UNIX", ""),
+                ("CARGO_CFG_TARGET_OS", "linux"),
+                ("CARGO_CFG_TARGET_FEATURE", "sse,sse2"),
+                ("CARGO_PKG_NAME", "sabry"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+
+        let enabled = |item: ItemMod| cfg.enabled(&item.attrs);
+
+        assert!(enabled(parse_quote! {mod a;}));
+        assert!(enabled(parse_quote! {#[cfg(feature = "dark-mode")] mod a;}));
+        assert!(!enabled(parse_quote! {#[cfg(feature = "light")] mod a;}));
+        assert!(!enabled(parse_quote! {#[cfg(test)] mod a;}));
+        assert!(enabled(parse_quote! {#[cfg(unix)] mod a;}));
+        assert!(enabled(
+            parse_quote! {#[cfg(target_feature = "sse2")] mod a;}
+        ));
+        assert!(enabled(
+            parse_quote! {#[cfg(all(unix, not(target_os = "macos")))] mod a;}
+        ));
+        assert!(!enabled(
+            parse_quote! {#[cfg(any(windows, target_os = "macos"))] mod a;}
+        ));
+        assert!(!enabled(
+            parse_quote! {#[cfg(unix)] #[cfg(feature = "light")] mod a;}
+        ));
+        assert!(enabled(parse_quote! {#[cfg(not(test))] mod a;}));
+    }
+}
@@ -1,64 +1,617 @@
 use std::{
+    collections::HashSet,
     fs, io,
     path::{Path, PathBuf},
 };
 
 use proc_macro2::TokenStream;
+use sabry_intrnl::{
+    config::manifest,
+    diagnostic::{Diagnostic, LineCol},
+    scoper::origin::ScopeOrigin,
+};
 use sabry_procmacro_impl::impls::styly;
-use syn::{spanned::Spanned, visit::Visit};
+use syn::{ext::IdentExt, spanned::Spanned, visit::Visit, Expr, Lit, Meta};
+
+use crate::cfg::CfgSet;
 
 #[derive(Debug, thiserror::Error)]
 pub enum FileVisitError {
     #[error("tt read file")]
     Read(#[from] io::Error),
-    #[error("styly! macro found, yet invalid")]
-    Styly(#[from] syn::Error),
 }
 
-/// Visit file, returning either [StylyVisitor] or error
-pub fn visit_file(path: &Path) -> Result<StylyVisitor, FileVisitError> {
+impl From<&FileVisitError> for Diagnostic {
+    fn from(err: &FileVisitError) -> Self {
+        match err {
+            FileVisitError::Read(e) => Diagnostic::error(e),
+        }
+    }
+}
+
+/// Visit file, returning either [StylyVisitor] or error.
+///
+/// Only failure to read the file is an error. Problems with its content are collected by the visitor
+pub fn visit_file(path: &Path, wrappers: &[String]) -> Result<StylyVisitor, FileVisitError> {
     let content = fs::read_to_string(path)?;
-    let code_file = syn::parse_file(&content)?;
 
     let mut styly_visitor = StylyVisitor {
         found_stylys: vec![],
+        diagnostics: vec![],
+        modules: vec![],
         path: path.to_owned(),
+        content,
+        tree: None,
+        wrappers: wrappers.iter().cloned().collect(),
+        aliases: HashSet::new(),
+    };
+    styly_visitor.visit_content();
+
+    Ok(styly_visitor)
+}
+
+/// File of the crate module, as found by following `mod` declarations
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModuleFile {
+    pub path: PathBuf,
+    /// Whether nested modules live next to the file, as with `lib.rs`, `mod.rs` or the `#[path]` given file,
+    /// rather than in the directory named after it
+    pub mod_rs: bool,
+}
+
+/// Visit the module file like [visit_file], skipping items disabled with `#[cfg]`,
+/// and collecting the files of `mod` declarations into [StylyVisitor::modules]
+pub fn visit_module(
+    module: &ModuleFile,
+    cfg: &CfgSet,
+    wrappers: &[String],
+) -> Result<StylyVisitor, FileVisitError> {
+    let content = fs::read_to_string(&module.path)?;
+
+    let file_dir = module
+        .path
+        .parent()
+        .map(|p| p.to_owned())
+        .unwrap_or_default();
+    let mod_dir = match module.mod_rs {
+        true => file_dir.clone(),
+        false => file_dir.join(module.path.file_stem().unwrap_or_default()),
     };
-    styly_visitor.visit_file(&code_file);
+
+    let mut styly_visitor = StylyVisitor {
+        found_stylys: vec![],
+        diagnostics: vec![],
+        modules: vec![],
+        path: module.path.clone(),
+        content,
+        tree: Some(ModuleTree {
+            cfg: cfg.clone(),
+            file_dir,
+            dirs: vec![mod_dir],
+        }),
+        wrappers: wrappers.iter().cloned().collect(),
+        aliases: HashSet::new(),
+    };
+    styly_visitor.visit_content();
 
     Ok(styly_visitor)
 }
 
+/// Where the module being visited is, within the module tree
+#[derive(Debug)]
+struct ModuleTree {
+    cfg: CfgSet,
+    /// Directory of the file, which top level `#[path]` attributes are relative to
+    file_dir: PathBuf,
+    /// Directories of nested modules of the file and its inline modules, the innermost last
+    dirs: Vec<PathBuf>,
+}
+
+impl StylyVisitor {
+    fn visit_content(&mut self) {
+        match syn::parse_file(&self.content) {
+            Ok(code_file) => {
+                // imports apply to the whole file, wherever they are
+                let mut aliases = StylyAliases::default();
+                aliases.visit_file(&code_file);
+                self.aliases = aliases.0;
+
+                // `#![cfg]` disables the whole module
+                if self.enabled(&code_file.attrs) {
+                    self.visit_file(&code_file)
+                }
+            }
+            // rustc will tell what is wrong anyway, and the file may use the syntax syn does not know
+            Err(e) => self.report(
+                Diagnostic::warning(&e)
+                    .context("Rust file can not be parsed, its styles are skipped"),
+                e.span(),
+            ),
+        }
+    }
+
+    /// Whether the item with the attributes is compiled. Always, unless the module tree is followed
+    fn enabled(&self, attrs: &[syn::Attribute]) -> bool {
+        self.tree.as_ref().map_or(true, |t| t.cfg.enabled(attrs))
+    }
+
+    /// File or directory of the module declared in the current one
+    fn module_file(&self, node: &syn::ItemMod) -> Option<ModuleFile> {
+        let tree = self.tree.as_ref()?;
+        let dir = tree.dirs.last()?;
+        let name = node.ident.unraw().to_string();
+
+        let path_attr = node.attrs.iter().find_map(|a| match &a.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("path") => match &nv.value {
+                Expr::Lit(l) => match &l.lit {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        });
+
+        let module = match (path_attr, &node.content) {
+            // inline module with `#[path]` just moves its nested modules
+            (Some(p), Some(_)) => ModuleFile {
+                path: dir.join(p),
+                mod_rs: true,
+            },
+            (None, Some(_)) => ModuleFile {
+                path: dir.join(name),
+                mod_rs: true,
+            },
+            // out of inline modules, `#[path]` is relative to the file
+            (Some(p), None) if tree.dirs.len() == 1 => ModuleFile {
+                path: tree.file_dir.join(p),
+                mod_rs: true,
+            },
+            (Some(p), None) => ModuleFile {
+                path: dir.join(p),
+                mod_rs: true,
+            },
+            (None, None) => match dir.join(format!("{name}.rs")) {
+                flat if flat.is_file() => ModuleFile {
+                    path: flat,
+                    mod_rs: false,
+                },
+                _ => ModuleFile {
+                    path: dir.join(name).join("mod.rs"),
+                    mod_rs: true,
+                },
+            },
+        };
+
+        Some(module)
+    }
+}
+
 /// Syn Visitor, which will look at every `styly!` macro,
 /// parse its input tokenstream and avaluate it, capturing [styly::MacroSyntax] result
 ///
-/// ## Panics
+/// The macro is recognized by its name, whatever path it is called with, by the names it is imported `as`,
+/// and in the statement position as well. Calls of the wrapper macros are read as `styly!` ones
 ///
-/// If either macro body is not a [TokenStream], or [styly::parse_macro_syntax] is not [Ok]
+/// Invalid macros are skipped, with the error collected into [StylyVisitor::diagnostics]
 #[derive(Debug)]
 pub struct StylyVisitor {
     pub found_stylys: Vec<styly::MacroSyntax>,
+    /// Problems found in the file, located in it
+    pub diagnostics: Vec<Diagnostic>,
+    /// Files of the enabled `mod` declarations, if visited with [visit_module]
+    pub modules: Vec<ModuleFile>,
     pub path: PathBuf,
+    content: String,
+    tree: Option<ModuleTree>,
+    /// Names of the user macros, which take the input of `styly!`
+    wrappers: HashSet<String>,
+    /// Names `styly!` is imported under in the file
+    aliases: HashSet<String>,
+}
+
+impl StylyVisitor {
+    /// Collect the diagnostic at the span of the file
+    fn report(&mut self, diagnostic: Diagnostic, span: proc_macro2::Span) {
+        // span lines are one-based
+        let start = span.start();
+        let diagnostic = diagnostic
+            .named(manifest::crate_relative(&self.path).display())
+            .at(LineCol {
+                line: start.line.saturating_sub(1),
+                column: start.column,
+            })
+            .in_code(&self.content);
+
+        self.diagnostics.push(diagnostic);
+    }
 }
 
 impl<'ast> Visit<'ast> for StylyVisitor {
+    fn visit_item(&mut self, node: &'ast syn::Item) {
+        if self.enabled(item_attrs(node)) {
+            syn::visit::visit_item(self, node);
+        }
+    }
+
+    fn visit_impl_item(&mut self, node: &'ast syn::ImplItem) {
+        let attrs = match node {
+            syn::ImplItem::Fn(f) => f.attrs.as_slice(),
+            syn::ImplItem::Macro(m) => &m.attrs,
+            _ => &[],
+        };
+        if self.enabled(attrs) {
+            syn::visit::visit_impl_item(self, node);
+        }
+    }
+
+    fn visit_item_mod(&mut self, node: &'ast syn::ItemMod) {
+        let Some(module) = self.module_file(node) else {
+            return syn::visit::visit_item_mod(self, node);
+        };
+
+        if node.content.is_some() {
+            if let Some(tree) = &mut self.tree {
+                tree.dirs.push(module.path);
+            }
+            syn::visit::visit_item_mod(self, node);
+            if let Some(tree) = &mut self.tree {
+                tree.dirs.pop();
+            }
+        } else if module.path.is_file() {
+            self.modules.push(module);
+        } else {
+            self.report(
+                Diagnostic::warning(format!(
+                    "Module file {} is not found, its styles are skipped",
+                    manifest::crate_relative(&module.path).display()
+                )),
+                node.ident.span(),
+            );
+        }
+    }
+
     fn visit_item_macro(&mut self, node: &'ast syn::ItemMacro) {
-        if node.mac.path.get_ident().is_some_and(|i| i == "styly") {
-            let body = node.mac.parse_body::<TokenStream>().unwrap_or_else(|e| {
-                panic!(
-                    "could not parse `styly!` macro at {:?}: {e:?}",
-                    node.mac.span()
-                )
-            });
-            let macro_data =
-                styly::parse_macro_syntax(body, self.path.parent().map(|p| p.to_owned()))
-                    .unwrap_or_else(|e| {
-                        panic!(
-                            "could not parse `styly!` macro at {:?}: {e:?}",
-                            node.mac.span()
-                        )
-                    });
-            self.found_stylys.push(macro_data);
+        // `macro_rules!` definitions are items as well, yet what is inside is not a call
+        if node.ident.is_none() {
+            self.visit_styly(&node.mac);
+        }
+    }
+
+    fn visit_stmt_macro(&mut self, node: &'ast syn::StmtMacro) {
+        if self.enabled(&node.attrs) {
+            self.visit_styly(&node.mac);
+        }
+    }
+
+    fn visit_expr_macro(&mut self, node: &'ast syn::ExprMacro) {
+        self.warn_misplaced(&node.mac, "an expression");
+        syn::visit::visit_expr_macro(self, node);
+    }
+
+    fn visit_impl_item_macro(&mut self, node: &'ast syn::ImplItemMacro) {
+        self.warn_misplaced(&node.mac, "an impl block");
+    }
+
+    fn visit_trait_item_macro(&mut self, node: &'ast syn::TraitItemMacro) {
+        self.warn_misplaced(&node.mac, "a trait");
+    }
+}
+
+/// Names `styly!` is imported under with `use ... as`
+#[derive(Default)]
+struct StylyAliases(HashSet<String>);
+
+impl<'ast> Visit<'ast> for StylyAliases {
+    fn visit_use_rename(&mut self, node: &'ast syn::UseRename) {
+        if node.ident == "styly" {
+            self.0.insert(node.rename.to_string());
+        }
+    }
+}
+
+impl StylyVisitor {
+    /// How the macro at the path is to be read, if it is `styly!` at all
+    fn styly_call(&self, path: &syn::Path) -> Option<StylyCall> {
+        let name = path.segments.last()?.ident.to_string();
+
+        if name == "styly" || (path.get_ident().is_some() && self.aliases.contains(&name)) {
+            Some(StylyCall::Styly)
+        } else if self.wrappers.contains(&name) {
+            Some(StylyCall::Wrapper(name))
+        } else {
+            None
+        }
+    }
+
+    /// Parse the `styly!` call, or the call of its wrapper
+    fn visit_styly(&mut self, mac: &syn::Macro) {
+        let Some(call) = self.styly_call(&mac.path) else {
+            return;
+        };
+
+        let parsed = mac.parse_body::<TokenStream>().and_then(|body| {
+            styly::parse_macro_syntax(body, self.path.parent().map(|p| p.to_owned()))
+        });
+        let mut macro_data = match (parsed, call) {
+            (Ok(md), _) => md,
+            (Err(e), StylyCall::Styly) => {
+                self.report(
+                    Diagnostic::error(&e).context("Invalid `styly!` macro"),
+                    e.span(),
+                );
+                return;
+            }
+            // the wrapper is not obliged to take just what `styly!` does
+            (Err(e), StylyCall::Wrapper(name)) => {
+                self.report(
+                    Diagnostic::warning(&e).context(format!(
+                        "`{name}!` input is not the one of `styly!`, so its styles are skipped"
+                    )),
+                    e.span(),
+                );
+                return;
+            }
+        };
+        macro_data.origin = ScopeOrigin::new(Some(&self.path));
+        macro_data.file = Some(self.path.clone());
+        self.found_stylys.push(macro_data);
+    }
+
+    /// Warn on `styly!` call, where it does not produce items for the scope to be bundled from
+    fn warn_misplaced(&mut self, mac: &syn::Macro, place: &str) {
+        if let Some(call) = self.styly_call(&mac.path) {
+            let name = match call {
+                StylyCall::Styly => "styly".to_string(),
+                StylyCall::Wrapper(name) => name,
+            };
+            self.report(
+                Diagnostic::warning(format!(
+                    "`{name}!` call in {place} is not understood, so its styles are skipped"
+                )),
+                mac.path.span(),
+            );
+        }
+    }
+}
+
+/// Macro call, which the styles are taken from
+enum StylyCall {
+    Styly,
+    /// User macro, taking the input of `styly!`
+    Wrapper(String),
+}
+
+/// Attributes of the item, if it may have them
+fn item_attrs(item: &syn::Item) -> &[syn::Attribute] {
+    use syn::Item;
+
+    match item {
+        Item::Const(i) => &i.attrs,
+        Item::Enum(i) => &i.attrs,
+        Item::ExternCrate(i) => &i.attrs,
+        Item::Fn(i) => &i.attrs,
+        Item::ForeignMod(i) => &i.attrs,
+        Item::Impl(i) => &i.attrs,
+        Item::Macro(i) => &i.attrs,
+        Item::Mod(i) => &i.attrs,
+        Item::Static(i) => &i.attrs,
+        Item::Struct(i) => &i.attrs,
+        Item::Trait(i) => &i.attrs,
+        Item::TraitAlias(i) => &i.attrs,
+        Item::Type(i) => &i.attrs,
+        Item::Union(i) => &i.attrs,
+        Item::Use(i) => &i.attrs,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use sabry_intrnl::diagnostic::{LineCol, Severity};
+
+    use super::{visit_file, visit_module, ModuleFile};
+    use crate::cfg::CfgSet;
+
+    #[test]
+    fn invalid_stylys_collected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let path = dir.join("stylys.rs");
+        fs::write(
+            &path,
+            r##"
+styly!(first {".a {color: red;}"});
+styly!(second {.b {content: r"x";}});
+styly!(third {".c {color: red;}"});
+styly!();
+"##,
+        )
+        .unwrap();
+        let visitor = visit_file(&path, &[]).unwrap();
+
+        let found = visitor
+            .found_stylys
+            .iter()
+            .map(|s| s.scope.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["first", "third"], found);
+        assert_eq!(2, visitor.diagnostics.len());
+        assert!(visitor
+            .diagnostics
+            .iter()
+            .all(|d| d.severity == Severity::Error));
+        assert_eq!(
+            Some(LineCol {
+                line: 2,
+                column: 28
+            }),
+            visitor.diagnostics[0].location
+        );
+        assert!(visitor.diagnostics[0]
+            .to_string()
+            .contains(r#"3 | styly!(second {.b {content: r"x";}});"#));
+
+        let path = dir.join("unparsable.rs");
+        fs::write(&path, "styly!(first {\".a {}\"});\nfn (").unwrap();
+        let visitor = visit_file(&path, &[]).unwrap();
+
+        assert!(visitor.found_stylys.is_empty());
+        assert_eq!(1, visitor.diagnostics.len());
+        assert_eq!(Severity::Warning, visitor.diagnostics[0].severity);
+        assert_eq!(1, visitor.diagnostics[0].location.unwrap().line);
+    }
+
+    #[test]
+    fn module_tree_followed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for sub in ["flat", "nested", "other", "inline"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
         }
+        for file in [
+            "flat.rs",
+            "flat/child.rs",
+            "nested/mod.rs",
+            "other/custom.rs",
+            "inline/deep.rs",
+            "dark.rs",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(
+            dir.join("lib.rs"),
+            r#"
+styly!(root {".a {}"});
+mod flat;
+mod nested;
+#[path = "other/custom.rs"]
+mod custom;
+#[cfg(feature = "darkmode")]
+mod dark;
+#[cfg(test)]
+mod tests {
+    styly!(test_only {".t {}"});
+}
+mod inline {
+    mod deep;
+    styly!(inline_one {".i {}"});
+}
+#[cfg(not(feature = "darkmode"))]
+mod light {
+    styly!(light_one {".l {}"});
+}
+mod missing;
+"#,
+        )
+        .unwrap();
+        fs::write(dir.join("flat.rs"), "mod child;").unwrap();
+
+        let root = ModuleFile {
+            path: dir.join("lib.rs"),
+            mod_rs: true,
+        };
+        let visitor = visit_module(&root, &CfgSet::default(), &[]).unwrap();
+
+        let found = visitor
+            .found_stylys
+            .iter()
+            .map(|s| s.scope.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["root", "inline_one", "light_one"], found);
+        let modules = visitor
+            .modules
+            .iter()
+            .map(|m| (m.path.strip_prefix(dir).unwrap().to_owned(), m.mod_rs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (PathBuf::from("flat.rs"), false),
+                (PathBuf::from("nested/mod.rs"), true),
+                (PathBuf::from("other/custom.rs"), true),
+                (PathBuf::from("inline/deep.rs"), false),
+            ],
+            modules
+        );
+        assert_eq!(1, visitor.diagnostics.len());
+        assert_eq!(Severity::Warning, visitor.diagnostics[0].severity);
+
+        let dark = CfgSet::from_vars([("CARGO_FEATURE_DARKMODE".into(), String::new())]);
+        let visitor = visit_module(&root, &dark, &[]).unwrap();
+        assert_eq!(2, visitor.found_stylys.len());
+        assert!(visitor.modules.iter().any(|m| m.path.ends_with("dark.rs")));
+
+        let visitor = visit_module(&visitor.modules[0], &dark, &[]).unwrap();
+        assert_eq!(
+            vec![ModuleFile {
+                path: dir.join("flat/child.rs"),
+                mod_rs: false,
+            }],
+            visitor.modules
+        );
+    }
+
+    #[test]
+    fn styly_calls_recognized() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let path = dir.join("calls.rs");
+        fs::write(
+            &path,
+            r#"
+use sabry::styly as scoped;
+
+sabry::styly!(qualified {".a {}"});
+::sabry::styly!(absolute {".a {}"});
+scoped!(aliased {".a {}"});
+component_style!(wrapped {".a {}"});
+component_style!(<Button> "style");
+
+fn component() {
+    styly!(statement {".a {}"});
+    mod inner {
+        styly!(nested {".a {}"});
+    }
+    let css = styly!(expression {".a {}"});
+}
+
+macro_rules! my_styly {
+    ($($t:tt)*) => { styly!($($t)*); };
+}
+"#,
+        )
+        .unwrap();
+        let visitor = visit_file(&path, &["component_style".to_string()]).unwrap();
+
+        let found = visitor
+            .found_stylys
+            .iter()
+            .map(|s| s.scope.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "qualified",
+                "absolute",
+                "aliased",
+                "wrapped",
+                "statement",
+                "nested"
+            ],
+            found
+        );
+
+        let warned = visitor
+            .diagnostics
+            .iter()
+            .map(|d| (d.severity, d.location.unwrap().line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(Severity::Warning, 7), (Severity::Warning, 14)],
+            warned
+        );
     }
 }
@@ -1,4 +1,7 @@
 #![doc = include_str!("../README.md")]
 
 pub mod buildmagic;
+pub mod cache;
+pub mod cfg;
 pub mod filevisit;
+pub mod pool;
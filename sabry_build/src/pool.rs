@@ -0,0 +1,70 @@
+use std::{
+    panic,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+/// Run the jobs on the given number of threads, returning outputs in the order of jobs.
+///
+/// Threads take the next job as soon as they are done with the previous one,
+/// so long jobs do not hold up the rest. With a single thread jobs run right here, one by one
+pub fn run<J, O, F>(jobs: &[J], threads: usize, f: F) -> Vec<O>
+where
+    J: Sync,
+    O: Send,
+    F: Fn(&J) -> O + Sync,
+{
+    let threads = threads.min(jobs.len());
+    if threads <= 1 {
+        return jobs.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (next, f) = (&next, &f);
+    let mut outputs = thread::scope(|s| {
+        let workers = (0..threads)
+            .map(|_| {
+                s.spawn(move || {
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(i) else {
+                            break;
+                        };
+                        done.push((i, f(job)));
+                    }
+                    done
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_else(|p| panic::resume_unwind(p)))
+            .collect::<Vec<_>>()
+    });
+
+    outputs.sort_by_key(|(i, _)| *i);
+    outputs.into_iter().map(|(_, o)| o).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn outputs_keep_jobs_order() {
+        let jobs = (0..64u64).collect::<Vec<_>>();
+        let square = |j: &u64| {
+            // later jobs are done earlier
+            thread::sleep(Duration::from_micros(64 - j));
+            j * j
+        };
+
+        let serial = super::run(&jobs, 1, square);
+        for threads in [2, 8, 100] {
+            assert_eq!(serial, super::run(&jobs, threads, square));
+        }
+        assert!(super::run(&[] as &[u64], 8, square).is_empty());
+    }
+}
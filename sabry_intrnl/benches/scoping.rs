@@ -0,0 +1,189 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use raffia::{
+    ast::{
+        ComplexSelector, CompoundSelector, PseudoClassSelectorArgKind, SimpleBlock, SimpleSelector,
+        Statement, Stylesheet,
+    },
+    ParserBuilder,
+};
+use sabry_intrnl::{
+    config::SabryHashConfig,
+    scoper::ArbitraryScope,
+    syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode, StylesheetAdapter},
+};
+
+/// Large stylesheet of nested rules, at-rules and pseudo-class arguments
+fn stylesheet(components: usize) -> String {
+    (0..components)
+        .map(|i| {
+            format!(
+                "
+.card{i} {{
+    color: red;
+    &-dark {{ color: black; }}
+    .title{i}:not(.hidden{i}), #anchor{i} > span {{ color: blue; }}
+    @media (max-width: 600px) {{
+        .body{i} :is(.a{i}, .b{i}) {{ display: grid; }}
+        :global(.external{i}) .inner{i} {{ color: white; }}
+    }}
+    @keyframes fade{i} {{ from {{ opacity: 0; }} }}
+    animation: fade{i} 1s;
+}}"
+            )
+        })
+        .collect()
+}
+
+/// Selector walk the way [StylesheetAdapter] did it before the single borrowing pass:
+/// statements are cloned at every nesting level, and the tree is walked once per selector kind
+mod cloning {
+    use super::*;
+
+    pub fn selectors_by<'s, T>(
+        stylesheet: &Stylesheet<'s>,
+        form: impl FnMut(SimpleSelector<'s>) -> Option<T>,
+    ) -> Vec<T> {
+        selectors_of(stylesheet.statements.iter().cloned())
+            .flat_map(|cs| cs.children)
+            .filter_map(form)
+            .collect()
+    }
+
+    fn selectors_of<'s>(
+        c: impl Iterator<Item = Statement<'s>>,
+    ) -> impl Iterator<Item = CompoundSelector<'s>> {
+        c.flat_map(|s| {
+            let thissels = s
+                .as_qualified_rule()
+                .map(|q| {
+                    q.selector
+                        .selectors
+                        .iter()
+                        .flat_map(compounds_of)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            selectors_of(nested_statements(&s).into_iter())
+                .chain(thissels)
+                .collect::<Vec<_>>()
+        })
+    }
+
+    fn compounds_of<'s>(complex: &ComplexSelector<'s>) -> Vec<CompoundSelector<'s>> {
+        complex
+            .children
+            .iter()
+            .filter_map(|sc| sc.as_compound_selector())
+            .flat_map(compound_with_nested)
+            .collect()
+    }
+
+    fn compound_with_nested<'s>(compound: &CompoundSelector<'s>) -> Vec<CompoundSelector<'s>> {
+        compound
+            .children
+            .iter()
+            .filter_map(|c| c.as_pseudo_class())
+            .filter(|cps| !StylesheetAdapter::is_glob_modifier(cps))
+            .filter_map(|cps| cps.arg.as_ref())
+            .flat_map(|arg| match &arg.kind {
+                PseudoClassSelectorArgKind::SelectorList(list) => {
+                    list.selectors.iter().flat_map(compounds_of).collect()
+                }
+                PseudoClassSelectorArgKind::RelativeSelectorList(list) => list
+                    .selectors
+                    .iter()
+                    .flat_map(|rel| compounds_of(&rel.complex_selector))
+                    .collect(),
+                PseudoClassSelectorArgKind::CompoundSelectorList(list) => list
+                    .selectors
+                    .iter()
+                    .flat_map(compound_with_nested)
+                    .collect(),
+                PseudoClassSelectorArgKind::CompoundSelector(c) => compound_with_nested(c),
+                _ => vec![],
+            })
+            .chain(std::iter::once(compound.clone()))
+            .collect()
+    }
+
+    fn nested_statements<'s>(s: &Statement<'s>) -> Vec<Statement<'s>> {
+        let blocks: Vec<&SimpleBlock<'s>> = match s {
+            Statement::QualifiedRule(q) => vec![&q.block],
+            Statement::AtRule(a) => a.block.iter().collect(),
+            Statement::UnknownSassAtRule(a) => a.block.iter().collect(),
+            Statement::SassIfAtRule(i) => std::iter::once(&i.if_clause.block)
+                .chain(i.else_if_clauses.iter().map(|c| &c.block))
+                .chain(i.else_clause.iter())
+                .collect(),
+            _ => vec![],
+        };
+
+        blocks
+            .into_iter()
+            .flat_map(|b| b.statements.iter().cloned())
+            .collect()
+    }
+}
+
+fn scoping(c: &mut Criterion) {
+    let config = SabryHashConfig {
+        use_item_names: true,
+        ..Default::default()
+    };
+
+    for components in [10, 400] {
+        let code = stylesheet(components);
+
+        let mut group = c.benchmark_group(format!("selectors {components}"));
+        group.bench_function("cloning walk per kind", |b| {
+            let stylesheet = ParserBuilder::new(&code)
+                .ignore_comments()
+                .syntax(OneSyntaxToRuleThemAll::Scss.into())
+                .build()
+                .parse::<Stylesheet>()
+                .unwrap();
+            b.iter(|| {
+                black_box((
+                    cloning::selectors_by(&stylesheet, |c| c.as_class().cloned()).len(),
+                    cloning::selectors_by(&stylesheet, |c| c.as_id().cloned()).len(),
+                    cloning::selectors_by(&stylesheet, |c| c.as_type().cloned()).len(),
+                    cloning::selectors_by(&stylesheet, |c| c.as_nesting().cloned()).len(),
+                    cloning::selectors_by(&stylesheet, |c| match c.as_pseudo_class() {
+                        Some(cps) if StylesheetAdapter::is_glob_modifier(cps) => Some(cps.clone()),
+                        _ => None,
+                    })
+                    .len(),
+                ))
+            })
+        });
+        group.bench_function("single borrowing pass", |b| {
+            let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code.as_str());
+            let adapter = StylesheetAdapter::new(&style).unwrap();
+            b.iter(|| {
+                let selectors = adapter.gather_selectors();
+                black_box((
+                    selectors.classes.len(),
+                    selectors.ids.len(),
+                    selectors.types.len(),
+                    selectors.nestings.len(),
+                    selectors.globs.len(),
+                ))
+            })
+        });
+        group.finish();
+
+        c.bench_function(&format!("hashed scope {components}"), |b| {
+            b.iter(|| {
+                let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code.as_str());
+                let scope =
+                    ArbitraryScope::from_source(syn::parse_str("scope").unwrap(), &style).unwrap();
+                let scope = scope.hashed(&config).unwrap();
+                black_box(scope.hashed_code.len())
+            })
+        });
+    }
+}
+
+criterion_group!(benches, scoping);
+criterion_main!(benches);
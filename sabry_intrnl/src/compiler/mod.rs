@@ -1,13 +1,22 @@
-use std::fmt::Debug;
+use std::{borrow::Cow, fmt::Debug};
+
+use parcel_sourcemap::{OriginalLocation, SourceMap, SourceMapError};
 
 use lightningcss::{
     error::{MinifyErrorKind, PrinterErrorKind},
     printer::PrinterOptions,
-    stylesheet::MinifyOptions,
+    stylesheet::{MinifyOptions, ParserFlags, ParserOptions},
     targets::Targets,
 };
 
-use crate::{config::SabryConfig, syntax::ostrta::OneSyntaxToRuleThemAll};
+use crate::{
+    config::{BehavScopeStage, SabryConfig},
+    diagnostic::{Diagnostic, LineCol},
+    syntax::ostrta::OneSyntaxToRuleThemAll,
+};
+use sourcemap::StyleOrigin;
+
+pub mod sourcemap;
 
 /// Convenience wrapper on Grass and Lightningcss
 pub struct CompilerAdapter {
@@ -19,27 +28,153 @@ impl CompilerAdapter {
         Self { config }
     }
 
-    /// Compile given SASS/SCSS into CSS
+    /// Compile given SASS/SCSS/CSS into CSS
     /// with respect to self.config
     pub fn compile_module(
         &self,
         syntax: OneSyntaxToRuleThemAll,
         code: &str,
+    ) -> Result<String, SabryCompilerError> {
+        let css = self.lightningcss(&self.precompile(syntax, code)?)?;
+
+        Ok(css)
+    }
+
+    /// CSS of the given code for lightningcss to work on:
+    /// grass-compiled SASS/SCSS, or plain CSS as is
+    fn precompile<'c>(
+        &self,
+        syntax: OneSyntaxToRuleThemAll,
+        code: &'c str,
+    ) -> Result<Cow<'c, str>, SabryCompilerError> {
+        match syntax {
+            OneSyntaxToRuleThemAll::Css => Ok(Cow::Borrowed(code)),
+            _ => Ok(Cow::Owned(self.grass(syntax, code)?)),
+        }
+    }
+
+    /// Compile given SASS/SCSS into CSS with grass only,
+    /// without the lightningcss pass
+    pub fn grass(
+        &self,
+        syntax: OneSyntaxToRuleThemAll,
+        code: &str,
     ) -> Result<String, SabryCompilerError> {
         let options = grass::Options::from(&self.config).input_syntax(syntax.into());
 
-        let css = self.lightningcss(&grass::from_string(code, &options)?)?;
+        Ok(grass::from_string(code, &options)?)
+    }
 
-        Ok(css)
+    /// Prepare given SASS/SCSS for scoping
+    /// with respect to self.config.hash.stage
+    ///
+    /// Returns the code as-is for the `source` stage and grass-compiled CSS for the `compiled` one,
+    /// along with the syntax the returned code should be parsed with.
+    /// Plain CSS has nothing to compile, so it is always returned as-is
+    pub fn scopable<'c>(
+        &self,
+        syntax: OneSyntaxToRuleThemAll,
+        code: &'c str,
+    ) -> Result<(OneSyntaxToRuleThemAll, Cow<'c, str>), SabryCompilerError> {
+        match (syntax, &self.config.hash.stage) {
+            (OneSyntaxToRuleThemAll::Css, _) | (_, BehavScopeStage::Source) => {
+                Ok((syntax, Cow::Borrowed(code)))
+            }
+            // plain CSS is valid SCSS, so raffia could parse it as is
+            (_, BehavScopeStage::Compiled) => Ok((
+                OneSyntaxToRuleThemAll::Scss,
+                Cow::Owned(self.grass(syntax, code)?),
+            )),
+        }
+    }
+
+    /// Compile the code scoped after [CompilerAdapter::scopable] into CSS
+    /// with respect to self.config.hash.stage
+    pub fn compile_scoped(
+        &self,
+        syntax: OneSyntaxToRuleThemAll,
+        code: &str,
+    ) -> Result<String, SabryCompilerError> {
+        match self.config.hash.stage {
+            BehavScopeStage::Source => self.compile_module(syntax, code),
+            BehavScopeStage::Compiled => self.lightningcss(code),
+        }
+    }
+
+    /// [Self::compile_scoped] along with the source map JSON of the result,
+    /// pointing to the given origin of the code
+    pub fn compile_scoped_mapped(
+        &self,
+        syntax: OneSyntaxToRuleThemAll,
+        code: &str,
+        origin: &StyleOrigin,
+    ) -> Result<(String, String), SabryCompilerError> {
+        let css = match self.config.hash.stage {
+            BehavScopeStage::Source => self.precompile(syntax, code)?,
+            BehavScopeStage::Compiled => Cow::Borrowed(code),
+        };
+
+        self.lightningcss_mapped(&css, |_, _| Some(origin.clone()))
+    }
+
+    /// [Self::lightningcss] along with the source map JSON of the result.
+    ///
+    /// Lightningcss maps the output onto the given css, and `resolve` tells
+    /// where line and column of the given css originally come from
+    pub fn lightningcss_mapped<R>(
+        &self,
+        css: &str,
+        mut resolve: R,
+    ) -> Result<(String, String), SabryCompilerError>
+    where
+        R: FnMut(u32, u32) -> Option<StyleOrigin>,
+    {
+        let mut light_map = SourceMap::new("/");
+        let css = self.lightningcss_to(css, Some(&mut light_map))?;
+
+        let mut map = SourceMap::new("/");
+        for mapping in light_map.get_mappings() {
+            let Some(origin) = mapping
+                .original
+                .and_then(|o| resolve(o.original_line, o.original_column))
+            else {
+                continue;
+            };
+
+            let source = map.add_source(&origin.file);
+            map.add_mapping(
+                mapping.generated_line,
+                mapping.generated_column,
+                Some(OriginalLocation::new(
+                    origin.line,
+                    origin.column,
+                    source,
+                    None,
+                )),
+            );
+        }
+
+        Ok((css, map.to_json(None)?))
     }
 
     /// Perform lightningcss transformations on given css
     /// with respect to self.config.css.minify on minification
     pub fn lightningcss(&self, css: &str) -> Result<String, SabryCompilerError> {
-        let mut lightsheet = match lightningcss::stylesheet::StyleSheet::parse(
-            css,
-            lightningcss::stylesheet::ParserOptions::default(),
-        ) {
+        self.lightningcss_to(css, None)
+    }
+
+    fn lightningcss_to(
+        &self,
+        css: &str,
+        source_map: Option<&mut SourceMap>,
+    ) -> Result<String, SabryCompilerError> {
+        // plain CSS may use the native nesting, grass output never does
+        let parser_options = ParserOptions {
+            flags: ParserFlags::NESTING,
+            ..Default::default()
+        };
+        let mut lightsheet = match lightningcss::stylesheet::StyleSheet::parse(css, parser_options)
+        {
             Ok(s) => s,
             Err(e) => {
                 return Err(SabryCompilerError::LightParse {
@@ -56,6 +191,7 @@ impl CompilerAdapter {
 
         let printer_options = PrinterOptions {
             minify: self.config.css.minify,
+            source_map,
             targets,
             ..Default::default()
         };
@@ -108,33 +244,285 @@ pub enum SabryCompilerError {
         kind: String,
         loc: Option<lightningcss::error::ErrorLocation>,
     },
+    #[error("Could not build source map")]
+    SourceMap(#[from] SourceMapError),
+}
+
+impl SabryCompilerError {
+    /// Where the error is in the compiled code, if known.
+    ///
+    /// Only grass errors in the code itself are located:
+    /// lightningcss works on the grass output, and other errors come from the used modules
+    pub fn location(&self) -> Option<LineCol> {
+        let diagnostic = Diagnostic::from(self);
+        match self {
+            Self::GrassCompile(_) if diagnostic.source_name.is_none() => diagnostic.location,
+            _ => None,
+        }
+    }
+}
+
+impl From<&SabryCompilerError> for Diagnostic {
+    fn from(err: &SabryCompilerError) -> Self {
+        fn light<T: std::fmt::Display>(err: &lightningcss::error::Error<T>) -> Diagnostic {
+            light_located(&err.kind, err.loc.as_ref())
+        }
+        fn light_located(
+            kind: impl std::fmt::Display,
+            loc: Option<&lightningcss::error::ErrorLocation>,
+        ) -> Diagnostic {
+            let diagnostic = Diagnostic::error(kind);
+            let Some(loc) = loc else {
+                return diagnostic;
+            };
+            // lightningcss columns are one-based, unlike lines
+            let diagnostic = diagnostic.at(LineCol {
+                line: loc.line as usize,
+                column: (loc.column as usize).saturating_sub(1),
+            });
+            match loc.filename.as_str() {
+                "" => diagnostic,
+                name => diagnostic.named(name),
+            }
+        }
+
+        match err {
+            SabryCompilerError::GrassCompile(e) => match e.as_ref().clone().kind() {
+                grass::ErrorKind::ParseError { message, loc, .. } => {
+                    let diagnostic = Diagnostic::error(message).at(LineCol {
+                        line: loc.begin.line,
+                        column: loc.begin.column,
+                    });
+                    // grass names the code compiled from string "stdin"
+                    match loc.file.name() {
+                        "stdin" => diagnostic,
+                        name => diagnostic.named(name),
+                    }
+                }
+                _ => Diagnostic::error(e),
+            },
+            SabryCompilerError::LightPrint(e) => light(e),
+            SabryCompilerError::LightMinify(e) => light(e),
+            SabryCompilerError::LightParse { kind, loc } => light_located(kind, loc.as_ref()),
+            SabryCompilerError::SourceMap(e) => Diagnostic::error(e),
+        }
+    }
 }
 
 impl Debug for SabryCompilerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let explain = match self {
-            Self::LightPrint(e) => format!(
-                "{} at {:?}",
-                e.kind,
-                e.loc
-                    .clone()
-                    .map(|l| format!("file {}, line {}, col {}", l.filename, l.line, l.column))
-            ),
-            Self::GrassCompile(err) => format!("{err}"),
-            Self::LightMinify(e) => format!(
-                "{} at {:?}",
-                e.kind,
-                e.loc
-                    .clone()
-                    .map(|l| format!("file {}, line {}, col {}", l.filename, l.line, l.column))
-            ),
-            Self::LightParse { kind, loc } => format!(
-                "{kind} at {:?}",
-                loc.clone()
-                    .map(|l| format!("file {}, line {}, col {}", l.filename, l.line, l.column))
-            ),
+        write!(f, "{}", Diagnostic::from(self).summary())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{
+        config::{BehavScopeStage, SabryConfig},
+        scoper::{hash::ScopeHash, ArbitraryScope, HashedScope},
+        syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode},
+    };
+
+    use super::{sourcemap::StyleOrigin, CompilerAdapter, Diagnostic};
+
+    #[test]
+    fn scope_compiled_stage() {
+        let code = "
+@each $size in sm, lg {
+    .btn-#{$size} {
+        color: red;
+    }
+}
+@mixin dark {
+    &-dark {
+        color: black;
+    }
+}
+.card {
+    @include dark;
+    :global(.theme) & {
+        color: white;
+    }
+}";
+        let mut config = SabryConfig::default();
+        config.css.minify = false;
+        config.hash.stage = BehavScopeStage::Compiled;
+
+        let compiler = CompilerAdapter::new(config);
+        let (syntax, scopable) = compiler
+            .scopable(OneSyntaxToRuleThemAll::Scss, code)
+            .unwrap();
+        let style = StyleCode::new(syntax, scopable);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(
+            ScopeHash::test_init("F2kf8nMs".into()),
+            scope,
+            &SabryConfig::default().hash,
+        )
+        .unwrap();
+
+        let members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| hs.html_ident.clone())
+            .collect::<HashSet<_>>();
+        let css = compiler.compile_scoped(syntax, &scope.hashed_code).unwrap();
+
+        assert!(members.iter().any(|m| m.ends_with("btn-sm")));
+        assert!(members.iter().any(|m| m.ends_with("btn-lg")));
+        assert!(members.iter().any(|m| m.ends_with("card-dark")));
+        assert!(css.contains(".F2kf8nMs.btn-lg"));
+        assert!(css.contains(".theme .F2kf8nMs.card"));
+    }
+
+    #[test]
+    fn scope_plain_css() {
+        let code = "
+.card {
+    color: red;
+    & .title {
+        color: black;
+    }
+    :global(.theme) & {
+        color: white;
+    }
+}
+@layer base {
+    .btn { inset: 0; }
+}";
+        let mut config = SabryConfig::default();
+        config.css.minify = false;
+        config.hash.stage = BehavScopeStage::Compiled;
+        // no native nesting there, so it is lowered
+        config.lightningcss.targets.chrome = Some("100".into());
+
+        let compiler = CompilerAdapter::new(config);
+        let (syntax, scopable) = compiler
+            .scopable(OneSyntaxToRuleThemAll::Css, code)
+            .unwrap();
+        assert!(matches!(syntax, OneSyntaxToRuleThemAll::Css));
+        assert_eq!(code, scopable);
+
+        let style = StyleCode::new(syntax, scopable);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(
+            ScopeHash::test_init("F2kf8nMs".into()),
+            scope,
+            &SabryConfig::default().hash,
+        )
+        .unwrap();
+        let css = compiler.compile_scoped(syntax, &scope.hashed_code).unwrap();
+
+        // `:global(.theme)` is left unscoped, while nesting is lowered
+        let expect_css = ".F2kf8nMs.card {
+  color: red;
+}
+
+.F2kf8nMs.card .F2kf8nMs.title {
+  color: #000;
+}
+
+.theme .F2kf8nMs.card {
+  color: #fff;
+}
+
+@layer base {
+  .F2kf8nMs.btn {
+    inset: 0;
+  }
+}
+";
+        assert_eq!(expect_css, css);
+    }
+
+    #[test]
+    fn scope_sass_source() {
+        let code = "
+=dark
+  &-dark
+    color: black
+.card
+  +dark
+  .title
+    .icon
+      color: red
+@keyframes fade
+  from
+    opacity: 0
+.tile
+  animation: fade 1s";
+        let mut config = SabryConfig::default();
+        config.css.minify = false;
+
+        let compiler = CompilerAdapter::new(config);
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(
+            ScopeHash::test_init("F2kf8nMs".into()),
+            scope,
+            &SabryConfig::default().hash,
+        )
+        .unwrap();
+        let css = compiler
+            .compile_scoped(OneSyntaxToRuleThemAll::Sass, &scope.hashed_code)
+            .unwrap();
+
+        assert!(css.contains(".F2kf8nMs.card-dark"));
+        assert!(css.contains(".F2kf8nMs.card .F2kf8nMs.title .F2kf8nMs.icon"));
+        assert!(css.contains("@keyframes F2kf8nMs-fade"));
+        assert!(css.contains("animation: 1s F2kf8nMs-fade"));
+    }
+
+    #[test]
+    fn scoped_source_map() {
+        let origin = StyleOrigin {
+            file: "src/lib.rs".into(),
+            line: 12,
+            column: 8,
         };
+        let compiler = CompilerAdapter::new(SabryConfig::default());
+        let (css, map) = compiler
+            .compile_scoped_mapped(
+                OneSyntaxToRuleThemAll::Scss,
+                ".a { color: red; .b { color: blue; } }",
+                &origin,
+            )
+            .unwrap();
+
+        let mut map = parcel_sourcemap::SourceMap::from_json("/", &map).unwrap();
+        let b = css.find(".a .b").unwrap() as u32;
+        let mapping = map.find_closest_mapping(0, b).unwrap().original.unwrap();
+
+        assert_eq!(
+            css,
+            compiler
+                .compile_scoped(
+                    OneSyntaxToRuleThemAll::Scss,
+                    ".a { color: red; .b { color: blue; } }"
+                )
+                .unwrap()
+        );
+        assert_eq!(vec!["src/lib.rs".to_string()], *map.get_sources());
+        assert_eq!((12, 8), (mapping.original_line, mapping.original_column));
+    }
+
+    #[test]
+    fn grass_error_diagnostic() {
+        let compiler = CompilerAdapter::new(SabryConfig::default());
+        let code = ".a {\n    color: red;\n    color: $undefined;\n}";
+        let err = compiler
+            .compile_module(OneSyntaxToRuleThemAll::Scss, code)
+            .unwrap_err();
+
+        let location = err.location().unwrap();
+        assert_eq!((2, 11), (location.line, location.column));
 
-        write!(f, "{explain}")
+        let diagnostic = Diagnostic::from(&err).in_code(code);
+        assert_eq!(None, diagnostic.source_name);
+        assert!(diagnostic
+            .to_string()
+            .ends_with(" --> 3:12\n  |\n3 |     color: $undefined;\n  |            ^"));
     }
 }
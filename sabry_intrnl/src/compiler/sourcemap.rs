@@ -0,0 +1,130 @@
+use parcel_sourcemap::{Mapping, SourceMap, SourceMapError};
+
+/// Where the style code starts in the original file: `.rs` file with `styly!` literal, or the style file itself.
+///
+/// Grass has no source maps, so compiled rules of the style are all mapped to its beginning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleOrigin {
+    /// File path, relative to the crate root
+    pub file: String,
+    /// Zero-based line
+    pub line: u32,
+    /// Zero-based column
+    pub column: u32,
+}
+
+/// Source maps of CSS pieces, glued one after another, to resolve positions in the whole.
+///
+/// Each piece is remembered with the position of the whole it starts at
+#[derive(Default)]
+pub struct GluedSourceMaps {
+    pieces: Vec<((u32, u32), Option<SourceMap>)>,
+}
+
+impl GluedSourceMaps {
+    /// Add the next piece, starting at the given line and column, with source map in JSON, if any
+    pub fn push(&mut self, start: (u32, u32), map: Option<&str>) -> Result<(), SourceMapError> {
+        let map = map.map(|m| SourceMap::from_json("/", m)).transpose()?;
+        self.pieces.push((start, map));
+        Ok(())
+    }
+
+    /// Original position of the position in the whole
+    pub fn resolve(&mut self, line: u32, column: u32) -> Option<StyleOrigin> {
+        let ((start_line, start_column), map) = self
+            .pieces
+            .iter_mut()
+            .rev()
+            .find(|(start, _)| *start <= (line, column))?;
+        let map = map.as_mut()?;
+
+        let local_line = line - *start_line;
+        let local_column = match local_line {
+            0 => column - *start_column,
+            _ => column,
+        };
+
+        match map.find_closest_mapping(local_line, local_column)? {
+            Mapping {
+                original: Some(o), ..
+            } => Some(StyleOrigin {
+                file: map.get_source(o.source).ok()?.to_string(),
+                line: o.original_line,
+                column: o.original_column,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Line and column the end of the code is at, the position next piece glued to it starts at
+pub fn end_of(code: &str) -> (u32, u32) {
+    let line = code.matches('\n').count() as u32;
+    let column = code.rsplit('\n').next().unwrap_or_default().len() as u32;
+    (line, column)
+}
+
+/// CSS code referencing its source map file, which is placed next to it
+pub fn with_map_comment(css: &str, map_path: &str) -> String {
+    let name = std::path::Path::new(map_path)
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+
+    format!("{css}\n/*# sourceMappingURL={name} */\n")
+}
+
+/// Source map JSON with `sourceRoot`, so relative sources resolve from the map file location.
+///
+/// `to` is the path of the map file relative to the crate root
+pub fn with_source_root(map: &str, to: &str) -> Result<String, SourceMapError> {
+    let depth = std::path::Path::new(to)
+        .parent()
+        .map(|p| p.components().count())
+        .unwrap_or_default();
+    let root = "../".repeat(depth);
+
+    SourceMap::from_json("/", map)?.to_json(Some(&root))
+}
+
+#[cfg(test)]
+mod test {
+    use parcel_sourcemap::{OriginalLocation, SourceMap};
+
+    use super::{end_of, with_source_root, GluedSourceMaps, StyleOrigin};
+
+    fn map_to(file: &str, line: u32) -> String {
+        let mut map = SourceMap::new("/");
+        let source = map.add_source(file);
+        map.add_mapping(0, 0, Some(OriginalLocation::new(line, 4, source, None)));
+        map.to_json(None).unwrap()
+    }
+
+    #[test]
+    fn glued_maps_resolve() {
+        let (a, b) = (".a{color:red}", ".b{color:red}\n.c{color:red}");
+
+        let mut glued = GluedSourceMaps::default();
+        glued.push((0, 0), Some(&map_to("src/a.rs", 10))).unwrap();
+        glued
+            .push(end_of(a), Some(&map_to("src/b.scss", 0)))
+            .unwrap();
+        glued.push(end_of(&format!("{a}{b}")), None).unwrap();
+
+        let origin = |file: &str, line| StyleOrigin {
+            file: file.into(),
+            line,
+            column: 4,
+        };
+        assert_eq!(Some(origin("src/a.rs", 10)), glued.resolve(0, 0));
+        assert_eq!(Some(origin("src/b.scss", 0)), glued.resolve(0, 13));
+        assert_eq!((1, 13), end_of(&format!("{a}{b}")));
+        assert_eq!(None, glued.resolve(1, 13));
+    }
+
+    #[test]
+    fn source_root_from_map_location() {
+        let map = with_source_root(&map_to("src/a.rs", 0), "target/static/style.css.map").unwrap();
+        assert!(map.contains(r#""sourceRoot":"../../""#));
+    }
+}
@@ -1,16 +1,160 @@
-use std::{env, fs, io};
+use std::{
+    any::Any,
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
 
 use serde::Deserialize;
+use toml::{Table, Value};
 
 use super::SabryConfig;
 
 pub const MANIFEST: &str = "Cargo.toml";
 pub const MANIFEST_DIR_ENV: &str = "CARGO_MANIFEST_DIR";
+/// Set by `buildy` for the crate being built, so `styly!` macros know the profile as well
+pub const PROFILE_ENV: &str = "SABRY_PROFILE";
+/// Set by `buildy` for the crate being built: `1` if scopes are qualified with module path, `0` if not,
+/// so `styly!` macros qualify them the same way
+pub const SCOPE_MODULES_ENV: &str = "SABRY_SCOPE_MODULES";
+/// Set by `buildy` for the crate being built to the file the mangling table is written into
+pub const MANGLE_TABLE_ENV: &str = "SABRY_MANGLE_TABLE";
+/// Set by `buildy` for the crate being built to the file the hash registry is written into
+pub const HASH_REGISTRY_ENV: &str = "SABRY_HASH_REGISTRY";
 
+/// Read the manifest, with \[package.metadata.sabry.profile.PROFILE\] table of the [active_profile]
+/// merged into the \[package.metadata.sabry\] one
 pub fn read_manifest() -> Result<ValuableManifest, ManifestError> {
     let manpath = format!("{}/{MANIFEST}", env::var(MANIFEST_DIR_ENV)?);
 
-    Ok(toml::de::from_str(&fs::read_to_string(manpath)?)?)
+    let mut manifest = toml::de::from_str::<Table>(&fs::read_to_string(manpath)?)?;
+    if let Some(profile) = active_profile() {
+        apply_profile(&mut manifest, &profile);
+    }
+
+    Ok(manifest.try_into()?)
+}
+
+/// Name of the cargo profile being built: `debug` or `release`
+///
+/// [PROFILE_ENV] goes first, then `PROFILE` and `OPT_LEVEL` cargo gives to build scripts.
+/// Proc macros are told neither, unless `buildy` sets [PROFILE_ENV], so the profile is unknown to them then
+pub fn active_profile() -> Option<String> {
+    if let Ok(profile) = env::var(PROFILE_ENV).or_else(|_| env::var("PROFILE")) {
+        return Some(profile);
+    }
+
+    env::var("OPT_LEVEL")
+        .ok()
+        .map(|level| if level == "0" { "debug" } else { "release" }.into())
+}
+
+/// Path relative to the crate root
+///
+/// Proc macros are not run from the crate root, so relative paths they read should be resolved with this
+pub fn crate_path(path: &str) -> PathBuf {
+    match env::var(MANIFEST_DIR_ENV) {
+        Ok(root) => PathBuf::from(root).join(path),
+        Err(_) => PathBuf::from(path),
+    }
+}
+
+/// Path relative to the crate root, if the path is inside of it.
+///
+/// Proc macros and build scripts are run from different directories, so paths are made absolute first
+pub fn crate_relative(path: &Path) -> PathBuf {
+    let absolute = |p: &Path| {
+        fs::canonicalize(p).unwrap_or_else(|_| {
+            env::current_dir()
+                .map(|d| d.join(p))
+                .unwrap_or_else(|_| p.to_owned())
+        })
+    };
+
+    let path = absolute(path);
+    match env::var(MANIFEST_DIR_ENV) {
+        Ok(root) => path
+            .strip_prefix(absolute(Path::new(&root)))
+            .map(|p| p.to_owned())
+            .unwrap_or(path),
+        Err(_) => path,
+    }
+}
+
+/// Name of the package, which manifest is in the given crate root, if any
+pub fn package_name(root: &Path) -> Option<String> {
+    let manifest =
+        toml::de::from_str::<Table>(&fs::read_to_string(root.join(MANIFEST)).ok()?).ok()?;
+
+    manifest
+        .get("package")
+        .and_then(|p| p.get("name"))
+        .and_then(|n| n.as_str())
+        .map(String::from)
+}
+
+/// File `buildy` has written for the crate being built, as told with the given env variable.
+///
+/// Every file is read once until it is changed, so `styly!` macros do not go to the disk on each expansion,
+/// while long-lived macro hosts, like rust-analyzer, still pick the rewritten ones up.
+/// [None] if no file is told, and an error if the told one can not be read
+pub fn built_file<T: Send + Sync + 'static>(
+    var: &str,
+    read: fn(&Path) -> io::Result<T>,
+) -> io::Result<Option<Arc<T>>> {
+    type Built = ((SystemTime, u64), Arc<dyn Any + Send + Sync>);
+    static FILES: OnceLock<Mutex<HashMap<PathBuf, Built>>> = OnceLock::new();
+
+    let Some(path) = env::var_os(var).map(PathBuf::from) else {
+        return Ok(None);
+    };
+    let metadata = fs::metadata(&path)?;
+    let version = (metadata.modified()?, metadata.len());
+
+    let mut files = FILES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some((known, file)) = files.get(&path) {
+        if let (true, Ok(file)) = (*known == version, file.clone().downcast::<T>()) {
+            return Ok(Some(file));
+        }
+    }
+
+    let file = Arc::new(read(&path)?);
+    files.insert(path, (version, file.clone()));
+    Ok(Some(file))
+}
+
+/// Merge the profile table of sabry metadata into sabry metadata itself
+pub fn apply_profile(manifest: &mut Table, profile: &str) {
+    let Some(sabry) = manifest
+        .get_mut("package")
+        .and_then(|p| p.get_mut("metadata"))
+        .and_then(|m| m.get_mut("sabry"))
+        .and_then(|s| s.as_table_mut())
+    else {
+        return;
+    };
+
+    if let Some(Value::Table(mut profiles)) = sabry.remove("profile") {
+        if let Some(Value::Table(overrides)) = profiles.remove(profile) {
+            merge(sabry, overrides);
+        }
+    }
+}
+
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(value)) => merge(base, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
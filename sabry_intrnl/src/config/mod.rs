@@ -1,9 +1,13 @@
 pub mod manifest;
+use std::sync::Arc;
+
 use manifest::{ManifestError, ValuableManifest};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+use crate::scoper::{mangle::MangleTable, registry::HashRegistry};
 
 /// Sabry configuration, as it is from package.metadata.sabry
-#[derive(Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct SabryConfig {
     #[serde(default = "SabryCssConfig::default")]
     pub css: SabryCssConfig,
@@ -11,6 +15,8 @@ pub struct SabryConfig {
     pub sass: SabrySassConfig,
     #[serde(default = "SabryHashConfig::default")]
     pub hash: SabryHashConfig,
+    #[serde(default = "SabryNamingConfig::default")]
+    pub naming: SabryNamingConfig,
     #[serde(default = "SabryGrassConfig::default")]
     pub grass: SabryGrassConfig,
     #[serde(default = "SabryLightCssConfig::default")]
@@ -37,19 +43,24 @@ impl From<&ValuableManifest> for SabryConfig {
 }
 
 /// Sabry `css` configuration table
-#[derive(Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct SabryCssConfig {
     pub bundle: Option<String>,
     pub scopes: Option<String>,
     pub prelude: Option<Vec<String>>,
     #[serde(default = "SabryCssConfig::default_minify")]
     pub minify: bool,
+    #[serde(default = "SabryCssConfig::default_sourcemap")]
+    pub sourcemap: bool,
 }
 
 impl SabryCssConfig {
     fn default_minify() -> bool {
         true
     }
+    fn default_sourcemap() -> bool {
+        false
+    }
 }
 
 impl Default for SabryCssConfig {
@@ -59,6 +70,7 @@ impl Default for SabryCssConfig {
             scopes: None,
             prelude: None,
             minify: Self::default_minify(),
+            sourcemap: Self::default_sourcemap(),
         }
     }
 }
@@ -66,7 +78,7 @@ impl Default for SabryCssConfig {
 /// Sabry `sass` configuration table
 ///
 /// (SASS/SCSS related config)
-#[derive(Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct SabrySassConfig {
     #[serde(default = "SabrySassConfig::default_scanroot")]
     pub scanroot: String,
@@ -76,6 +88,10 @@ pub struct SabrySassConfig {
     pub intermediate_dir: String,
     #[serde(default = "SabrySassConfig::default_module_name_collision")]
     pub module_name_collision: BehavSassModCollision,
+    #[serde(default = "SabrySassConfig::default_discovery")]
+    pub discovery: BehavStyleDiscovery,
+    /// Names of the user macros, which take the input of `styly!` and pass it to one
+    pub styly_wrappers: Option<Vec<String>>,
 }
 
 impl SabrySassConfig {
@@ -88,6 +104,9 @@ impl SabrySassConfig {
     fn default_module_name_collision() -> BehavSassModCollision {
         BehavSassModCollision::default()
     }
+    fn default_discovery() -> BehavStyleDiscovery {
+        BehavStyleDiscovery::default()
+    }
 }
 
 impl Default for SabrySassConfig {
@@ -98,12 +117,14 @@ impl Default for SabrySassConfig {
             prelude: None,
             intermediate_dir: Self::default_intermediate_dir(),
             module_name_collision: Self::default_module_name_collision(),
+            discovery: Self::default_discovery(),
+            styly_wrappers: None,
         }
     }
 }
 
 /// Sabry `hash` configuration table
-#[derive(Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct SabryHashConfig {
     #[serde(default = "SabryHashConfig::default_size")]
     pub size: usize,
@@ -117,6 +138,28 @@ pub struct SabryHashConfig {
     pub use_code_size: bool,
     #[serde(default = "SabryHashConfig::default_use_code_text")]
     pub use_code_text: bool,
+    #[serde(default = "SabryHashConfig::default_stage")]
+    pub stage: BehavScopeStage,
+    #[serde(default = "SabryHashConfig::default_custom_properties")]
+    pub custom_properties: bool,
+    #[serde(default = "SabryHashConfig::default_encoding")]
+    pub encoding: BehavHashEncoding,
+    /// Exact hash length in symbols. Takes precedence over `size`
+    #[serde(default, deserialize_with = "SabryHashConfig::hash_length")]
+    pub length: Option<usize>,
+    #[serde(default, deserialize_with = "SabryHashConfig::hash_prefix")]
+    pub prefix: Option<String>,
+    /// Naming template for scoped identifiers, like `[scope]__[local]--[hash]`
+    pub template: Option<String>,
+    /// Rename scoped identifiers into the shortest unique ones across the bundle
+    #[serde(default = "SabryHashConfig::default_mangle")]
+    pub mangle: bool,
+    /// Mangling table assigned by the builder. Unless set, the one `buildy` has written for the crate is used
+    #[serde(skip)]
+    pub mangle_names: Option<Arc<MangleTable>>,
+    /// Hash registry resolved by the builder. Unless set, the one `buildy` has written for the crate is used
+    #[serde(skip)]
+    pub registry: Option<Arc<HashRegistry>>,
 }
 
 impl SabryHashConfig {
@@ -138,6 +181,48 @@ impl SabryHashConfig {
     fn default_use_code_text() -> bool {
         false
     }
+    fn default_stage() -> BehavScopeStage {
+        BehavScopeStage::default()
+    }
+    fn default_custom_properties() -> bool {
+        false
+    }
+    fn default_encoding() -> BehavHashEncoding {
+        BehavHashEncoding::default()
+    }
+    fn default_mangle() -> bool {
+        false
+    }
+
+    /// Hash length, which is at least one symbol
+    fn hash_length<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+        match usize::deserialize(deserializer)? {
+            0 => Err(serde::de::Error::custom(
+                "hash length has to be at least 1, as the empty hash does not scope anything",
+            )),
+            length => Ok(Some(length)),
+        }
+    }
+
+    /// Hash prefix, which has to be the start of CSS identifier:
+    /// letters, digits, `-` and `_`, not starting with a digit or `-` followed by a digit
+    fn hash_prefix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+        let prefix = String::deserialize(deserializer)?;
+
+        let valid = prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            && !prefix.starts_with(|c: char| c.is_ascii_digit())
+            && !prefix
+                .strip_prefix('-')
+                .is_some_and(|p| p.starts_with(|c: char| c.is_ascii_digit()));
+        match valid {
+            true => Ok(Some(prefix)),
+            false => Err(serde::de::Error::custom(format!(
+                "hash prefix '{prefix}' is not the start of CSS identifier: only letters, digits, `-` and `_` are allowed, not starting with a digit or `-` followed by a digit"
+            ))),
+        }
+    }
 }
 
 impl Default for SabryHashConfig {
@@ -149,16 +234,89 @@ impl Default for SabryHashConfig {
             use_code_size: Self::default_use_code_size(),
             use_code_text: Self::default_use_code_text(),
             use_item_names: Self::default_use_item_names(),
+            stage: Self::default_stage(),
+            custom_properties: Self::default_custom_properties(),
+            encoding: Self::default_encoding(),
+            length: None,
+            prefix: None,
+            template: None,
+            mangle: Self::default_mangle(),
+            mangle_names: None,
+            registry: None,
+        }
+    }
+}
+
+/// Sabry `naming` configuration table: how scope members are named in rust
+#[derive(Debug, Deserialize, Clone)]
+pub struct SabryNamingConfig {
+    #[serde(default = "SabryNamingConfig::default_convention")]
+    pub convention: BehavNamingConvention,
+    /// Prepended to the members of id selectors
+    #[serde(
+        default = "SabryNamingConfig::default_id_prefix",
+        deserialize_with = "SabryNamingConfig::member_prefix"
+    )]
+    pub id_prefix: String,
+    /// Prepended to the members of tagname selectors
+    #[serde(
+        default = "SabryNamingConfig::default_tag_prefix",
+        deserialize_with = "SabryNamingConfig::member_prefix"
+    )]
+    pub tag_prefix: String,
+    #[serde(default = "SabryNamingConfig::default_collision")]
+    pub collision: BehavNameCollision,
+}
+
+impl SabryNamingConfig {
+    fn default_convention() -> BehavNamingConvention {
+        BehavNamingConvention::default()
+    }
+    fn default_id_prefix() -> String {
+        "the".into()
+    }
+    fn default_tag_prefix() -> String {
+        "any".into()
+    }
+    fn default_collision() -> BehavNameCollision {
+        BehavNameCollision::default()
+    }
+
+    /// Member prefix, which has to be the start of rust identifier:
+    /// letters, digits and `_`, not starting with a digit
+    fn member_prefix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        let prefix = String::deserialize(deserializer)?;
+
+        let valid = prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !prefix.starts_with(|c: char| c.is_ascii_digit());
+        match valid {
+            true => Ok(prefix),
+            false => Err(serde::de::Error::custom(format!(
+                "member prefix '{prefix}' is not the start of rust identifier: only letters, digits and `_` are allowed, not starting with a digit"
+            ))),
+        }
+    }
+}
+
+impl Default for SabryNamingConfig {
+    fn default() -> Self {
+        Self {
+            convention: Self::default_convention(),
+            id_prefix: Self::default_id_prefix(),
+            tag_prefix: Self::default_tag_prefix(),
+            collision: Self::default_collision(),
         }
     }
 }
 
 /// [grass] specific configuration for sabry
-#[derive(Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct SabryGrassConfig {}
 
 /// [lightningcss] specific configuration for sabry
-#[derive(Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct SabryLightCssConfig {
     pub targets: SabryLightTargets,
 }
@@ -170,7 +328,7 @@ pub struct SabryLightCssConfig {
 ///
 /// This structure is a part of [SabryConfig], which faces developer with more convenient
 /// `safari = "13.2"` syntax.
-#[derive(Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct SabryLightTargets {
     pub android: Option<String>,
     pub chrome: Option<String>,
@@ -216,40 +374,188 @@ impl From<SabryLightTargets> for lightningcss::targets::Browsers {
 }
 
 /// \[package.metadata.sabry.sass\].module_name_collision option
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum BehavSassModCollision {
+    #[default]
     Merge,
     Error,
 }
 
-impl Default for BehavSassModCollision {
-    fn default() -> Self {
-        Self::Merge
-    }
+/// \[package.metadata.sabry.sass\].discovery option
+///
+/// How the builder finds `styly!` calls of the crate
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BehavStyleDiscovery {
+    /// Every rust file under the scanroot
+    #[default]
+    Walk,
+    /// Files of the module tree, starting from the crate root, with respect to `#[cfg]` attributes
+    Modules,
 }
 
 /// \[package.metadata.sabry.hash\].collision option
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum BehavHashCollision {
     Error,
+    #[default]
     Ignore,
+    /// Salt and hash the colliding scope again, until it does not collide
+    Rehash,
 }
 
-impl Default for BehavHashCollision {
-    fn default() -> Self {
-        Self::Ignore
-    }
+/// \[package.metadata.sabry.hash\].stage option
+///
+/// Which code is scoped: the original SASS/SCSS source, or CSS compiled from it
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BehavScopeStage {
+    #[default]
+    Source,
+    Compiled,
+}
+
+/// \[package.metadata.sabry.naming\].convention option
+///
+/// Case of the scope member names, `.foo-bar` is taken for example
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BehavNamingConvention {
+    /// `fooBar`, with the prefix glued as is, like `themain` for `#main`
+    #[default]
+    Camel,
+    /// `foo_bar`
+    Snake,
+    /// `FOO_BAR`
+    Screaming,
+    /// `foo_bar`, and `fooBar` for `.fooBar`: the identifier as is, with chars rust does not allow replaced by `_`
+    Verbatim,
+}
+
+/// \[package.metadata.sabry.naming\].collision option
+///
+/// What to do when different selectors make the same member name, like `.foo-bar` and `.fooBar`
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BehavNameCollision {
+    /// Sort the colliding selector sources, and suffix all but the first one with `_2`, `_3` and so on,
+    /// so names do not depend on the order of the code
+    #[default]
+    Suffix,
+    Error,
+}
+
+/// \[package.metadata.sabry.hash\].encoding option
+///
+/// How hash bytes are turned into symbols
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BehavHashEncoding {
+    #[default]
+    Base64,
+    Base32,
+    Base62,
+    Hex,
 }
 
 #[cfg(test)]
 mod test {
-    use super::{manifest, SabryConfig};
+    use super::{
+        manifest::{self, ValuableManifest},
+        SabryConfig,
+    };
 
     #[test]
     fn empty_manifest_still_worth_a_read() {
         let manifest = manifest::read_manifest().unwrap();
         let _conf = SabryConfig::from(&manifest);
     }
+
+    #[test]
+    fn package_name_read() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(Some("sabry_intrnl".into()), manifest::package_name(root));
+        assert_eq!(None, manifest::package_name(&root.join("src")));
+    }
+
+    #[test]
+    fn profile_overrides() {
+        let source = r#"
+[package.metadata.sabry]
+css.minify = false
+hash.size = 4
+hash.template = "[scope]__[local]--[hash]"
+
+[package.metadata.sabry.profile.debug]
+hash.template = "[scope]__[local]"
+
+[package.metadata.sabry.profile.release]
+css.minify = true
+hash.mangle = true
+"#;
+
+        for (profile, minify, template, mangle) in [
+            ("debug", false, "[scope]__[local]", false),
+            ("release", true, "[scope]__[local]--[hash]", true),
+            ("bench", false, "[scope]__[local]--[hash]", false),
+        ] {
+            let mut manifest = toml::de::from_str::<toml::Table>(source).unwrap();
+            manifest::apply_profile(&mut manifest, profile);
+            let conf = SabryConfig::from(&manifest.try_into::<ValuableManifest>().unwrap());
+
+            assert_eq!(minify, conf.css.minify);
+            assert_eq!(4, conf.hash.size);
+            assert_eq!(Some(template.to_string()), conf.hash.template);
+            assert_eq!(mangle, conf.hash.mangle);
+        }
+    }
+
+    #[test]
+    fn member_prefixes_validated() {
+        let naming = |prefix: &str| {
+            toml::de::from_str::<ValuableManifest>(&format!(
+                "[package.metadata.sabry]\nnaming.id_prefix = \"{prefix}\""
+            ))
+        };
+
+        for prefix in ["", "the", "id_", "_x2"] {
+            let manifest = naming(prefix).unwrap();
+            assert_eq!(prefix, SabryConfig::from(&manifest).naming.id_prefix);
+        }
+        for prefix in ["id-", "#", "4id", "a b"] {
+            let error = naming(prefix).err().unwrap().to_string();
+            assert!(error.contains("is not the start of rust identifier"));
+        }
+    }
+
+    #[test]
+    fn hash_length_and_prefix_validated() {
+        let hash = |option: &str| {
+            toml::de::from_str::<ValuableManifest>(&format!(
+                "[package.metadata.sabry]\nhash.{option}"
+            ))
+        };
+
+        let manifest = hash("length = 1").unwrap();
+        assert_eq!(Some(1), SabryConfig::from(&manifest).hash.length);
+        let error = hash("length = 0").err().unwrap().to_string();
+        assert!(error.contains("hash length has to be at least 1"));
+
+        for prefix in ["", "app-", "-app", "--", "_x2", "x-2"] {
+            let manifest = hash(&format!("prefix = \"{prefix}\"")).unwrap();
+            assert_eq!(
+                Some(prefix),
+                SabryConfig::from(&manifest).hash.prefix.as_deref()
+            );
+        }
+        for prefix in ["4app", "-4", "app.", "a b", "#"] {
+            let error = hash(&format!("prefix = \"{prefix}\""))
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(error.contains("is not the start of CSS identifier"));
+        }
+    }
 }
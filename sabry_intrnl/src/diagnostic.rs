@@ -0,0 +1,225 @@
+use std::{fmt::Display, ops::Range};
+
+/// How bad the [Diagnostic] is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Zero-based line and byte column in the code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl LineCol {
+    /// Location of the byte offset in the code
+    pub fn of_offset(code: &str, offset: usize) -> Self {
+        let offset = char_boundary(code, offset);
+        let before = &code[..offset];
+        let line_start = before.rfind('\n').map_or(0, |n| n + 1);
+
+        Self {
+            line: before.matches('\n').count(),
+            column: offset - line_start,
+        }
+    }
+
+    /// Byte offset of the location in the code, clamped to the code
+    pub fn offset_in(&self, code: &str) -> usize {
+        let line_start = code
+            .split_inclusive('\n')
+            .take(self.line)
+            .map(str::len)
+            .sum::<usize>();
+        char_boundary(code, line_start + self.column)
+    }
+}
+
+/// Error or warning about the style code, shared by all the sabry errors.
+///
+/// Displays as the message, followed by `name:line:col` and the code frame, whatever of those is known:
+///
+/// ```text
+/// Unexpected token
+///  --> src/style.scss:3:5
+///   |
+/// 3 |     colr red;
+///   |     ^^^^
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the code the diagnostic is about, like the file path
+    pub source_name: Option<String>,
+    /// Byte span of the offending code
+    pub span: Option<Range<usize>>,
+    /// Where the offending code starts
+    pub location: Option<LineCol>,
+    /// Rendered code frame, pointing at the offending code
+    pub frame: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Display) -> Self {
+        Self {
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn warning(message: impl Display) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    /// Prefix the message with what was going on
+    pub fn context(mut self, context: impl Display) -> Self {
+        self.message = format!("{context}: {}", self.message);
+        self
+    }
+
+    pub fn named(mut self, source_name: impl Display) -> Self {
+        self.source_name = Some(source_name.to_string());
+        self
+    }
+
+    pub fn with_span(mut self, span: Range<usize>) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn at(mut self, location: LineCol) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Resolve the span and location against the code they are in, and render the frame.
+    ///
+    /// Diagnostic knowing neither of them is left as is
+    pub fn in_code(mut self, code: &str) -> Self {
+        let span = match (&self.span, &self.location) {
+            (Some(span), _) => span.clone(),
+            (None, Some(location)) => {
+                let start = location.offset_in(code);
+                start..start
+            }
+            (None, None) => return self,
+        };
+        let start = char_boundary(code, span.start);
+        let end = char_boundary(code, span.end.max(start));
+        let location = LineCol::of_offset(code, start);
+
+        let line_start = start - location.column;
+        let line = code[line_start..].lines().next().unwrap_or_default();
+        // carets do not go past the line
+        let marked = code[start..end.min(line_start + line.len()).max(start)]
+            .chars()
+            .count()
+            .max(1);
+        // location given is the one the reader knows the code by, so the frame follows it
+        let number = (self.location.get_or_insert(location).line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+
+        self.frame = Some(format!(
+            "{gutter} |\n{number} | {line}\n{gutter} | {}{}",
+            " ".repeat(code[line_start..start].chars().count()),
+            "^".repeat(marked)
+        ));
+        self.span = Some(start..end);
+        self
+    }
+
+    /// Message with the location in a single line, for the places a frame does not fit in
+    pub fn summary(&self) -> String {
+        match self.position() {
+            Some(position) => format!("{} at {position}", self.message),
+            None => self.message.clone(),
+        }
+    }
+
+    /// `name:line:col` with one-based line and column, whatever of those is known
+    fn position(&self) -> Option<String> {
+        let location = self
+            .location
+            .map(|l| format!("{}:{}", l.line + 1, l.column + 1));
+
+        match (&self.source_name, location) {
+            (Some(name), Some(location)) => Some(format!("{name}:{location}")),
+            (Some(name), None) => Some(name.clone()),
+            (None, location) => location,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(position) = self.position() {
+            write!(f, "\n --> {position}")?;
+        }
+        if let Some(frame) = &self.frame {
+            write!(f, "\n{frame}")?;
+        }
+        Ok(())
+    }
+}
+
+fn char_boundary(code: &str, offset: usize) -> usize {
+    let mut offset = offset.min(code.len());
+    while !code.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Diagnostic, LineCol};
+
+    #[test]
+    fn diagnostic_rendering() {
+        let code = ".a {\n    color: red;\n    colr red;\n}";
+        let start = code.find("colr").unwrap();
+
+        let diagnostic = Diagnostic::error("Unexpected token")
+            .named("src/style.scss")
+            .with_span(start..start + 4)
+            .in_code(code);
+        assert_eq!(Some(LineCol { line: 2, column: 4 }), diagnostic.location);
+        assert_eq!(
+            "Unexpected token\n --> src/style.scss:3:5\n  |\n3 |     colr red;\n  |     ^^^^",
+            diagnostic.to_string()
+        );
+        assert_eq!(
+            "Unexpected token at src/style.scss:3:5",
+            diagnostic.summary()
+        );
+
+        let diagnostic = Diagnostic::warning("Unknown property")
+            .at(LineCol { line: 1, column: 4 })
+            .in_code(code);
+        assert_eq!(Some(9..9), diagnostic.span);
+        assert_eq!(
+            "Unknown property\n --> 2:5\n  |\n2 |     color: red;\n  |     ^",
+            diagnostic.to_string()
+        );
+
+        assert_eq!("Oops", Diagnostic::error("Oops").in_code(code).to_string());
+    }
+}
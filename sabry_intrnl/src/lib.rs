@@ -2,5 +2,6 @@
 
 pub mod compiler;
 pub mod config;
+pub mod diagnostic;
 pub mod scoper;
 pub mod syntax;
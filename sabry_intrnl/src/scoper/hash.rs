@@ -1,19 +1,70 @@
+use std::collections::BTreeMap;
+
 use base64::Engine;
 
-use crate::config::SabryHashConfig;
+use crate::{
+    config::{BehavHashCollision, BehavHashEncoding, SabryHashConfig},
+    syntax::Selectors,
+};
 
 use super::{apply_basic_rusty_member_gen_rules, ArbitraryScope};
 
 /// Convenience wrapper for String-being-a-hash
+///
+/// Also holds the naming template for scoped identifiers if any,
+/// with `[scope]` and `[hash]` already substituted,
+/// and mangled names of the scope identifiers if mangling is on
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ScopeHash(String);
+pub struct ScopeHash {
+    hash: String,
+    template: Option<String>,
+    mangled: BTreeMap<String, String>,
+}
 
 impl ScopeHash {
+    /// Hash the scope. With [BehavHashCollision::Rehash] the scope is salted
+    /// as the hash registry written by the builder says
     pub fn new(scope: &ArbitraryScope, config: &SabryHashConfig) -> Self {
+        Self::with_selectors(scope, &scope.adapter().gather_selectors(), config)
+    }
+
+    /// [Self::new] with the selectors of the scope already gathered, so the stylesheet is not walked again
+    pub fn with_selectors(
+        scope: &ArbitraryScope,
+        selectors: &Selectors,
+        config: &SabryHashConfig,
+    ) -> Self {
+        let salt = match config.collision {
+            BehavHashCollision::Rehash => config
+                .registry
+                .as_ref()
+                .map(|r| r.salt_of(&scope.qualified_name()))
+                .unwrap_or_default(),
+            _ => 0,
+        };
+
+        Self::salted_with(scope, selectors, config, salt)
+    }
+
+    /// Hash the scope with the given salt. Zero salt means no salt at all
+    pub fn salted(scope: &ArbitraryScope, config: &SabryHashConfig, salt: usize) -> Self {
+        Self::salted_with(scope, &scope.adapter().gather_selectors(), config, salt)
+    }
+
+    fn salted_with(
+        scope: &ArbitraryScope,
+        selectors: &Selectors,
+        config: &SabryHashConfig,
+        salt: usize,
+    ) -> Self {
         let mut hasher = blake3::Hasher::new();
 
+        if salt > 0 {
+            hasher.update(&salt.to_le_bytes());
+        }
+
         if config.use_scope_name {
-            hasher.update(scope.name.to_string().as_bytes());
+            hasher.update(scope.qualified_name().as_bytes());
         }
         if config.use_code_text {
             hasher.update(scope.adapter().source().as_bytes());
@@ -22,15 +73,14 @@ impl ScopeHash {
             hasher.update(&scope.adapter().source().len().to_ne_bytes());
         }
         if config.use_item_names {
-            let classes = scope.adapter().class_selectors();
-            let ids = scope.adapter().id_selectors();
             //# types/tags dont participate
             //# child-parent dont participate
 
-            let merged_items = classes
+            let merged_items = selectors
+                .classes
                 .iter()
                 .map(|s| &s.name)
-                .chain(ids.iter().map(|i| &i.name))
+                .chain(selectors.ids.iter().map(|i| &i.name))
                 .map(|ident| ident.as_literal())
                 .filter_map(|mbl| mbl.map(|l| l.raw))
                 .collect::<String>();
@@ -38,39 +88,168 @@ impl ScopeHash {
             hasher.update(merged_items.as_bytes());
         }
 
-        let size = if config.size >= blake3::OUT_LEN {
-            blake3::OUT_LEN
-        } else {
-            config.size
+        let hash = match config.length {
+            // exact length in symbols: encode surely enough bytes and cut
+            Some(length) => {
+                let mut bytes = vec![0; length * 2];
+                hasher.finalize_xof().fill(&mut bytes);
+
+                Self::encode(config.encoding, &bytes)
+                    .chars()
+                    .take(length)
+                    .collect::<String>()
+            }
+            // "size" is size of hash in bytes here
+            None => {
+                let size = if config.size >= blake3::OUT_LEN {
+                    blake3::OUT_LEN
+                } else {
+                    config.size
+                };
+
+                let hash = hasher.finalize();
+                Self::encode(config.encoding, &hash.as_bytes()[..size])
+            }
         };
+        let hash = format!(
+            "{}{}",
+            config.prefix.as_deref().unwrap_or_default(),
+            Self::css_valid(hash)
+        );
 
-        let hash = hasher.finalize();
-        let hash = hash.as_bytes();
+        let template = config.template.as_ref().map(|t| {
+            t.replace("[scope]", &scope.name.to_string())
+                .replace("[hash]", &hash)
+        });
 
-        //? This isn't right. "size" is size of hash in symbols.
-        // naaah, its fine :D (Dan)
-        let hash = base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(&hash[..size]);
-        let hash = apply_basic_rusty_member_gen_rules(&hash);
+        // mangling table is assigned by the builder, and `styly!` takes the one it has written.
+        // Until then, identifiers are not mangled
+        let mangled = if config.mangle {
+            let prefix = config.prefix.as_deref().unwrap_or_default();
+            config
+                .mangle_names
+                .as_ref()
+                .map(|t| t.of_scope(&hash))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(l, m)| (l, format!("{prefix}{m}")))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
 
-        Self(hash)
+        Self {
+            hash,
+            template,
+            mangled,
+        }
     }
 
     pub fn as_str(&self) -> &str {
-        &self.0
+        &self.hash
+    }
+
+    /// Whether scoped identifiers are renamed by the naming template or the mangling table
+    pub fn renames_locals(&self) -> bool {
+        self.template.is_some() || !self.mangled.is_empty()
+    }
+
+    /// Name for the scoped identifier: mangled one, or the one with naming template if any
+    pub fn templated(&self, local: &str) -> Option<String> {
+        self.mangled
+            .get(local)
+            .cloned()
+            .or_else(|| self.template.as_ref().map(|t| t.replace("[local]", local)))
+    }
+
+    fn encode(encoding: BehavHashEncoding, bytes: &[u8]) -> String {
+        match encoding {
+            BehavHashEncoding::Base64 => {
+                let hash = base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(bytes);
+                apply_basic_rusty_member_gen_rules(&hash)
+            }
+            BehavHashEncoding::Base32 => {
+                const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+                let mut encoded = String::with_capacity(bytes.len() * 8 / 5 + 1);
+                let (mut buffer, mut bits) = (0u16, 0u8);
+                for b in bytes {
+                    buffer = (buffer << 8) | *b as u16;
+                    bits += 8;
+                    while bits >= 5 {
+                        bits -= 5;
+                        encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+                    }
+                }
+                if bits > 0 {
+                    encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+                }
+                encoded
+            }
+            BehavHashEncoding::Base62 => {
+                const ALPHABET: &[u8; 62] =
+                    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+                // bytes are the big-endian number, which is divided by 62 until nothing left
+                let mut number = bytes.to_vec();
+                let mut encoded = vec![];
+                while number.iter().any(|b| *b != 0) {
+                    let mut remainder = 0u16;
+                    for b in number.iter_mut() {
+                        let acc = (remainder << 8) | *b as u16;
+                        *b = (acc / 62) as u8;
+                        remainder = acc % 62;
+                    }
+                    encoded.push(ALPHABET[remainder as usize] as char);
+                }
+                encoded.iter().rev().collect()
+            }
+            BehavHashEncoding::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+
+    /// CSS identifier can not start with a digit, so it is replaced with a letter
+    fn css_valid(hash: String) -> String {
+        let mut chars = hash.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_digit() => {
+                let letter = (b'g' + (c as u8 - b'0')) as char;
+                std::iter::once(letter).chain(chars).collect()
+            }
+            _ => hash,
+        }
     }
 
     #[cfg(test)]
     pub fn test_init(v: String) -> Self {
-        Self(v)
+        Self {
+            hash: v,
+            template: None,
+            mangled: BTreeMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn test_init_templated(v: String, scope: &str, template: &str) -> Self {
+        let template = template.replace("[scope]", scope).replace("[hash]", &v);
+        Self {
+            hash: v,
+            template: Some(template),
+            mangled: BTreeMap::new(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use syn::Ident;
 
     use crate::{
-        config::SabryHashConfig, scoper::ArbitraryScope, syntax::ostrta::OneSyntaxToRuleThemAll,
+        config::{BehavHashCollision, BehavHashEncoding, SabryHashConfig},
+        scoper::{mangle::MangleTable, registry::HashRegistry, ArbitraryScope},
+        syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode},
     };
 
     use super::ScopeHash;
@@ -79,22 +258,152 @@ mod test {
     fn hash_matches() {
         let source = ".cls1{color:red; &-dark{color: black} #id1 {color:green;} div {color:blue;}} .cls3#id2{color: black;}";
 
-        let scope1 = ArbitraryScope::from_source(
-            OneSyntaxToRuleThemAll::Scss,
-            syn::parse_str::<Ident>("lasifudm").unwrap(),
-            source,
-        )
-        .unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope1 =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
         let hash1 = ScopeHash::new(&scope1, &SabryHashConfig::default());
 
-        let scope2 = ArbitraryScope::from_source(
-            OneSyntaxToRuleThemAll::Scss,
-            syn::parse_str::<Ident>("lasifudm").unwrap(),
-            source,
-        )
-        .unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope2 =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
         let hash2 = ScopeHash::new(&scope2, &SabryHashConfig::default());
 
         assert_eq!(hash1, hash2);
     }
+
+    #[test]
+    fn hash_salted_by_registry() {
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, ".cls1{color:red;}");
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
+
+        let mut registry = HashRegistry::default();
+        registry.insert(scope.qualified_name(), 2, "whatever".into());
+        let mut config = SabryHashConfig {
+            registry: Some(Arc::new(registry)),
+            ..Default::default()
+        };
+
+        // the registry is only followed when collisions are resolved with it
+        let unsalted = ScopeHash::salted(&scope, &config, 0);
+        assert_eq!(unsalted, ScopeHash::new(&scope, &config));
+
+        config.collision = BehavHashCollision::Rehash;
+        let salted = ScopeHash::new(&scope, &config);
+        assert_eq!(ScopeHash::salted(&scope, &config, 2), salted);
+        assert_ne!(unsalted, salted);
+    }
+
+    #[test]
+    fn hash_encodings() {
+        let source = ".cls1{color:red;}";
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
+
+        for (encoding, alphabet) in [
+            (
+                BehavHashEncoding::Base64,
+                "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_",
+            ),
+            (
+                BehavHashEncoding::Base32,
+                "abcdefghijklmnopqrstuvwxyz234567",
+            ),
+            (
+                BehavHashEncoding::Base62,
+                "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
+            ),
+            (BehavHashEncoding::Hex, "abcdefghijklmnop0123456789"),
+        ] {
+            for length in 1..40 {
+                let config = SabryHashConfig {
+                    encoding,
+                    length: Some(length),
+                    ..Default::default()
+                };
+                let hash = ScopeHash::new(&scope, &config);
+
+                assert_eq!(length, hash.as_str().len());
+                assert!(hash.as_str().chars().all(|c| alphabet.contains(c)));
+                assert!(!hash.as_str().starts_with(|c: char| c.is_ascii_digit()));
+            }
+        }
+    }
+
+    #[test]
+    fn hash_prefix_and_template() {
+        let source = ".cls1{color:red;}";
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
+        let config = SabryHashConfig {
+            encoding: BehavHashEncoding::Hex,
+            length: Some(4),
+            prefix: Some("pj-".into()),
+            template: Some("[scope]__[local]--[hash]".into()),
+            ..Default::default()
+        };
+        let hash = ScopeHash::new(&scope, &config);
+
+        assert!(hash.as_str().starts_with("pj-"));
+        assert_eq!(7, hash.as_str().len());
+        assert_eq!(
+            Some(format!("lasifudm__cls1--{}", hash.as_str())),
+            hash.templated("cls1")
+        );
+    }
+
+    #[test]
+    fn hash_mangled() {
+        let source = ".cls1{color:red;}";
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
+        let mut config = SabryHashConfig {
+            prefix: Some("x".into()),
+            ..Default::default()
+        };
+        let hash = ScopeHash::new(&scope, &config);
+        assert_eq!(None, hash.templated("cls1"));
+
+        config.mangle_names = Some(Arc::new(MangleTable::assign([
+            (hash.as_str().to_string(), "cls1".to_string()),
+            ("zzz".to_string(), "cls1".to_string()),
+        ])));
+        config.mangle = true;
+        let hash = ScopeHash::new(&scope, &config);
+
+        assert_eq!(Some("xa".to_string()), hash.templated("cls1"));
+        assert_eq!(None, hash.templated("cls2"));
+    }
+
+    #[test]
+    fn hash_salted() {
+        let source = ".cls1{color:red;}";
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("lasifudm").unwrap(), &style)
+                .unwrap();
+        let config = SabryHashConfig::default();
+
+        assert_eq!(
+            ScopeHash::new(&scope, &config),
+            ScopeHash::salted(&scope, &config, 0)
+        );
+        assert_eq!(
+            ScopeHash::salted(&scope, &config, 1),
+            ScopeHash::salted(&scope, &config, 1)
+        );
+        assert_ne!(
+            ScopeHash::salted(&scope, &config, 0),
+            ScopeHash::salted(&scope, &config, 1)
+        );
+    }
 }
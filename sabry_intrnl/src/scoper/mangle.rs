@@ -0,0 +1,165 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::config::manifest::{self, MANGLE_TABLE_ENV};
+
+/// Whole-bundle table of mangled names.
+///
+/// Maps scope hash and local identifier onto the shortest unique identifier.
+/// Written by the builder, so `styly!` macros are able to read it and stay consistent with the bundle
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MangleTable(BTreeMap<(String, String), String>);
+
+impl MangleTable {
+    /// Assign the shortest unique identifiers to the given (hash, local) pairs.
+    ///
+    /// Pairs are sorted first, so assignment does not depend on the order they came in
+    pub fn assign(keys: impl IntoIterator<Item = (String, String)>) -> Self {
+        let keys = keys.into_iter().collect::<BTreeSet<_>>();
+
+        Self(
+            keys.into_iter()
+                .enumerate()
+                .map(|(i, k)| (k, Self::short_ident(i)))
+                .collect(),
+        )
+    }
+
+    /// All the mangled names of the scope with the given hash, by local identifier
+    pub fn of_scope(&self, hash: &str) -> BTreeMap<String, String> {
+        self.0
+            .iter()
+            .filter(|((h, _), _)| h == hash)
+            .map(|((_, l), m)| (l.clone(), m.clone()))
+            .collect()
+    }
+
+    /// Table `buildy` has written for the crate being built, as told with [MANGLE_TABLE_ENV].
+    ///
+    /// Read once until it is changed, see [manifest::built_file]
+    pub fn built() -> io::Result<Option<Arc<Self>>> {
+        manifest::built_file(MANGLE_TABLE_ENV, Self::read)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let table = fs::read_to_string(path)?
+            .lines()
+            .filter_map(|l| {
+                let mut parts = l.split_whitespace();
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(h), Some(l), Some(m)) => {
+                        Some(((h.to_string(), l.to_string()), m.to_string()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Ok(Self(table))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let table = self
+            .0
+            .iter()
+            .map(|((h, l), m)| format!("{h} {l} {m}\n"))
+            .collect::<String>();
+
+        fs::write(path, table)
+    }
+
+    /// N-th shortest identifier: letter first, then letters and digits
+    fn short_ident(n: usize) -> String {
+        const FIRST: &[u8; 52] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        const REST: &[u8; 62] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+        let mut ident = String::from(FIRST[n % FIRST.len()] as char);
+        let mut n = n / FIRST.len();
+        while n > 0 {
+            n -= 1;
+            ident.push(REST[n % REST.len()] as char);
+            n /= REST.len();
+        }
+        ident
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        env, fs,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::config::manifest::MANGLE_TABLE_ENV;
+
+    use super::MangleTable;
+
+    #[test]
+    fn short_idents_are_unique() {
+        let idents = (0..10_000)
+            .map(MangleTable::short_ident)
+            .collect::<HashSet<_>>();
+
+        assert_eq!(10_000, idents.len());
+        assert!(idents.iter().all(|i| i.starts_with(char::is_alphabetic)));
+        assert_eq!("a", MangleTable::short_ident(0));
+        assert_eq!(1, MangleTable::short_ident(51).len());
+        assert_eq!(2, MangleTable::short_ident(52).len());
+    }
+
+    #[test]
+    fn table_roundtrip() {
+        let table = MangleTable::assign([
+            ("Hsh2".to_string(), "btn".to_string()),
+            ("Hsh1".to_string(), "btn".to_string()),
+            ("Hsh1".to_string(), "fade".to_string()),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mangle");
+        table.write(&path).unwrap();
+
+        assert_eq!(table, MangleTable::read(&path).unwrap());
+        assert_eq!(
+            [("btn", "a"), ("fade", "b")]
+                .map(|(l, m)| (l.to_string(), m.to_string()))
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+            table.of_scope("Hsh1")
+        );
+    }
+
+    #[test]
+    fn built_table_follows_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mangle");
+        let table = |local: &str| MangleTable::assign([("Hsh1".to_string(), local.to_string())]);
+        env::set_var(MANGLE_TABLE_ENV, &path);
+
+        table("btn").write(&path).unwrap();
+        assert_eq!(table("btn"), *MangleTable::built().unwrap().unwrap());
+
+        // rewritten by another build, while the macro host lives on
+        table("fade").write(&path).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(table("fade"), *MangleTable::built().unwrap().unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert!(MangleTable::built().is_err());
+    }
+}
@@ -1,27 +1,57 @@
-use std::fmt::Debug;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Debug,
+};
 
 use cfg_if::cfg_if;
 use hash::ScopeHash;
+use origin::ScopeOrigin;
 use raffia::{Span, Spanned};
 use regex::Regex;
 
 use crate::{
-    config::SabryHashConfig,
-    syntax::{ostrta::OneSyntaxToRuleThemAll, StylesheetAdapter},
+    config::{BehavNameCollision, BehavNamingConvention, SabryHashConfig, SabryNamingConfig},
+    diagnostic::Diagnostic,
+    syntax::{
+        idents::{AuthorIdent, AuthorIdentKind},
+        Selectors, StyleCode, StylesheetAdapter,
+    },
 };
 
 pub mod hash;
+pub mod mangle;
+pub mod origin;
+pub mod registry;
+
+/// Whether `lepty-scoping` is on, as it changes the scoped CSS of the very same code
+pub const LEPTY_SCOPING: bool = cfg!(feature = "lepty-scoping");
 
 #[derive(thiserror::Error)]
 pub enum ScopeError {
     #[error("Raffia reports parse error")]
-    Raffia(raffia::error::ErrorKind, String),
+    Raffia(raffia::error::ErrorKind, String, Span),
+    #[error("Suffixed nesting `{0}` can not follow the naming template or the mangling table, as the suffix is glued onto the renamed parent. Spell the selector out, or use the `compiled` hash stage")]
+    RenamedNesting(String, Span),
+}
+
+impl From<&ScopeError> for Diagnostic {
+    fn from(err: &ScopeError) -> Self {
+        match err {
+            ScopeError::Raffia(kind, _, span) => {
+                Diagnostic::error(kind).with_span(span.start..span.end)
+            }
+            ScopeError::RenamedNesting(_, span) => {
+                Diagnostic::error(err).with_span(span.start..span.end)
+            }
+        }
+    }
 }
 
 impl Debug for ScopeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let a = match self {
-            Self::Raffia(kind, source) => format!("{kind}: at {source}"),
+            Self::Raffia(kind, source, _) => format!("{kind}: at {source}"),
+            Self::RenamedNesting(..) => self.to_string(),
         };
 
         write!(f, "{a}")
@@ -32,33 +62,62 @@ impl Debug for ScopeError {
 pub struct ArbitraryScope<'s> {
     /// Ident name for the current scope
     pub name: syn::Ident,
+    /// Where the scope is defined
+    pub origin: ScopeOrigin,
     adapter: StylesheetAdapter<'s>,
 }
 
 impl<'s> ArbitraryScope<'s> {
-    /// Parse given source code with given syntax and assign given name
-    pub fn from_source(
-        syntax: OneSyntaxToRuleThemAll,
-        name: syn::Ident,
-        source: &'s str,
-    ) -> Result<Self, ScopeError> {
-        let adapter = match StylesheetAdapter::new(source, syntax) {
+    /// Parse given style code and assign given name
+    pub fn from_source(name: syn::Ident, code: &'s StyleCode<'s>) -> Result<Self, ScopeError> {
+        let adapter = match StylesheetAdapter::new(code) {
             Ok(a) => a,
             Err(e) => {
-                let source = &source[e.span.start..e.span.end];
-                return Err(ScopeError::Raffia(e.kind, source.to_string()));
+                let source = &code.code[e.span.start..e.span.end];
+                return Err(ScopeError::Raffia(e.kind, source.to_string(), e.span));
             }
         };
 
-        Ok(Self { adapter, name })
+        Ok(Self {
+            adapter,
+            name,
+            origin: ScopeOrigin::default(),
+        })
+    }
+
+    /// Set where the scope is defined, so it gets fully qualified name
+    pub fn with_origin(mut self, origin: ScopeOrigin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Fully qualified scope name, which identifies the scope in hashing, file names and errors
+    pub fn qualified_name(&self) -> String {
+        self.origin.qualify(&self.name)
+    }
+
+    /// Name for the CSS chunk file of the scope, without extension
+    pub fn chunk_name(&self) -> String {
+        self.origin.chunk(&self.name)
     }
 
     /// Consume arbitrary scope and create a [HashedScope] in its basis
     ///
-    /// This function calls [HashedScope::new] under the hood
+    /// Does the same as [ScopeHash::new] followed by [HashedScope::new],
+    /// yet walks the stylesheet only once
     pub fn hashed(self, config: &SabryHashConfig) -> Result<HashedScope<'s>, ScopeError> {
-        let hash = ScopeHash::new(&self, config);
-        HashedScope::new(hash, self)
+        let selectors = self.adapter.gather_selectors();
+        let hash = ScopeHash::with_selectors(&self, &selectors, config);
+        HashedScope::check_nestings(&hash, &self, &selectors)?;
+        let (hashed_code, hashed_selectors) =
+            HashedScope::hash_code(&hash, &self, &selectors, config);
+
+        Ok(HashedScope {
+            original_scope: self,
+            hashed_code,
+            hashed_selectors,
+            hash,
+        })
     }
 
     /// Borrow the underlying [StylesheetAdapter]
@@ -85,58 +144,137 @@ pub struct HashedScope<'s> {
 
 impl<'s> HashedScope<'s> {
     /// Consume [ArbitraryScope],
-    /// calculate the hash,
-    /// hash all the supported selectors
+    /// hash all the supported selectors with the given hash
     /// and construct hashed source code
     ///
     /// Heavy operation.
-    pub fn new(hash: ScopeHash, scope: ArbitraryScope<'s>) -> Result<Self, ScopeError> {
+    pub fn new(
+        hash: ScopeHash,
+        scope: ArbitraryScope<'s>,
+        config: &SabryHashConfig,
+    ) -> Result<Self, ScopeError> {
+        let selectors = scope.adapter().gather_selectors();
+        Self::check_nestings(&hash, &scope, &selectors)?;
+        let (hashed_code, hashed_selectors) = Self::hash_code(&hash, &scope, &selectors, config);
+
+        Ok(Self {
+            original_scope: scope,
+            hashed_code,
+            hashed_selectors,
+            hash,
+        })
+    }
+
+    /// `&-dark` is scoped by the very parent name, so it can not be renamed on its own
+    fn check_nestings(
+        hash: &ScopeHash,
+        scope: &ArbitraryScope<'s>,
+        selectors: &Selectors<'_, 's>,
+    ) -> Result<(), ScopeError> {
+        if !hash.renames_locals() {
+            return Ok(());
+        }
+        match selectors.nestings.iter().find(|ns| ns.suffix.is_some()) {
+            Some(ns) => {
+                let span = scope.adapter().original_span(&ns.span);
+                let source = scope.adapter().source()[span.start..span.end].to_string();
+                Err(ScopeError::RenamedNesting(source, span))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Hashed source code along with the hashed selectors, sorted by their position
+    fn hash_code(
+        hash: &ScopeHash,
+        scope: &ArbitraryScope<'s>,
+        selectors: &Selectors<'_, 's>,
+        config: &SabryHashConfig,
+    ) -> (String, Vec<HashedSelector>) {
         let origin_code = scope.adapter().source();
-        let mut hashed_code = String::with_capacity(scope.adapter().source().len());
+        let parsed_code = scope.adapter().parsed();
+        let mut hashed_code = String::with_capacity(origin_code.len());
+
+        // interpolated selectors like `.btn-#{$size}` are only known after sass compilation,
+        // so they are left as is here. Use the `compiled` hash stage to get those scoped
 
         // get classes
-        let classes = scope.adapter().class_selectors();
-        let classes = classes.iter().map(|c| HashedSelector::from_class(&hash, c));
+        let classes = selectors
+            .classes
+            .iter()
+            .filter(|c| c.name.as_literal().is_some())
+            .map(|c| HashedSelector::from_class(hash, c));
 
         // get ids
-        let ids = scope.adapter().id_selectors();
-        let ids = ids.iter().map(|c| HashedSelector::from_id(&hash, c));
+        let ids = selectors
+            .ids
+            .iter()
+            .filter(|c| c.name.as_literal().is_some())
+            .map(|c| HashedSelector::from_id(hash, c));
 
         // get tags
-        let tags = scope.adapter().type_selectors();
-        let tags = tags
+        let tags = selectors
+            .types
             .iter()
             .filter_map(|t| t.as_tag_name())
-            .map(|c| HashedSelector::from_tag(&hash, c));
+            .filter(|t| t.name.name.as_literal().is_some())
+            .map(|c| HashedSelector::from_tag(hash, c));
 
         // get global selectors
-        let globs = scope.adapter().glob_modified_selectors();
-        let globs = globs
+        let globs = selectors
+            .globs
             .iter()
             .filter_map(|g| {
                 // we do ignore the latter selectors from :global(a, b) because there's no clear decision:
                 // how should we treat :glob(a, b) c {}
                 // as `a c {}` `b c {}`
                 // or as `a, b c {}` ?
-                g.arg.clone().and_then(|a| {
-                    if let raffia::ast::PseudoClassSelectorArgKind::SelectorList(list) = a.kind {
-                        list.selectors
-                            .first()
-                            .cloned()
-                            .map(|fcl| (g.span.clone(), fcl.span.clone()))
-                    } else {
-                        None
-                    }
-                })
+                match &g.arg.as_ref()?.kind {
+                    raffia::ast::PseudoClassSelectorArgKind::SelectorList(list) => list
+                        .selectors
+                        .first()
+                        .map(|fcl| (g.span.clone(), fcl.span.clone())),
+                    _ => None,
+                }
+            })
+            .map(|c| HashedSelector::from_glob_mod(hash, c, parsed_code));
+
+        // get author-defined identifiers: keyframes, containers, counter styles and properties
+        let idents = selectors.author_idents(config.custom_properties);
+        let idents = idents
+            .iter()
+            .map(|i| HashedSelector::from_author_ident(hash, i));
+
+        // get global(whatever) values
+        let glob_values = selectors.glob_modified_values();
+        let glob_values = glob_values
+            .iter()
+            .filter_map(|f| {
+                let first = f.args.first()?.span();
+                let last = f.args.last()?.span();
+                Some((
+                    f.span.clone(),
+                    Span {
+                        start: first.start,
+                        end: last.end,
+                    },
+                ))
             })
-            .map(|c| HashedSelector::from_glob_mod(&hash, c, scope.adapter().source()));
+            .map(|c| HashedSelector::from_glob_mod(hash, c, parsed_code));
 
         let mut hashed_selectors = classes
             .chain(ids)
             .chain(tags)
             .chain(globs)
+            .chain(idents)
+            .chain(glob_values)
             .collect::<Vec<_>>();
 
+        // spans are in the parsed code, which is not the written one for SASS
+        for sel in &mut hashed_selectors {
+            sel.sel.map_spans(|s| scope.adapter().original_span(s));
+        }
+
         // sorting by span start is important because of how hashed code construction works
         hashed_selectors.sort_by(|a, b| {
             let astart = a.sel.as_arbitrary().span.start;
@@ -154,12 +292,7 @@ impl<'s> HashedScope<'s> {
         }
         hashed_code.push_str(&origin_code[last_term_span..]);
 
-        Ok(Self {
-            original_scope: scope,
-            hashed_code,
-            hashed_selectors,
-            hash,
-        })
+        (hashed_code, hashed_selectors)
     }
 }
 
@@ -228,11 +361,32 @@ impl HashedSelector {
         }
     }
 
+    /// Construct the [HashedSelector], with given hash and author-defined identifier
+    pub fn from_author_ident(hash: &ScopeHash, ident: &AuthorIdent) -> Self {
+        let sel = ScopedSelector::from_author_ident(ident);
+        let css_ident = Self::make_hashed_css(&sel, hash);
+        let html_ident = Self::make_hashed_html(&sel, hash);
+
+        Self {
+            sel,
+            css_ident,
+            html_ident,
+        }
+    }
+
     /// Transform this selector into hashed version for CSS-ish language
     ///
     /// Cooperates with the [HashedSelector::make_hashed_html], so
     /// whats this function returns will be usable in HTML with to_hashed_html_def()
     pub fn make_hashed_css(value: &ScopedSelector, hash: &ScopeHash) -> String {
+        // naming template, if any, renames the identifier instead of composition
+        if let Some(templated) = value.local().and_then(|l| hash.templated(l)) {
+            return match value {
+                ScopedSelector::Property(_) => format!("--{templated}"),
+                _ => templated,
+            };
+        }
+
         match value {
             // Class scoping is done with class composition
             ScopedSelector::Class(a) => {
@@ -259,6 +413,13 @@ impl HashedSelector {
                 }
             }
             ScopedSelector::Glob { raw, .. } => raw.clone(),
+            // Author-defined identifiers are not selectors, so there is nothing to compose with
+            ScopedSelector::Keyframes(a)
+            | ScopedSelector::Container(a)
+            | ScopedSelector::CounterStyle(a) => format!("{}-{}", hash.as_str(), a.ident),
+            ScopedSelector::Property(a) => {
+                format!("--{}-{}", hash.as_str(), a.ident.trim_start_matches("--"))
+            }
         }
     }
 
@@ -269,15 +430,21 @@ impl HashedSelector {
     ///
     /// Not every hashed selector is presentable for HTML-ish use: like `div`, in that case
     /// returns [None]
-    pub fn make_hashed_html(value: &ScopedSelector, _hash: &ScopeHash) -> Option<String> {
+    pub fn make_hashed_html(value: &ScopedSelector, hash: &ScopeHash) -> Option<String> {
         match value {
+            // Renamed with naming template, so nothing to compose with
+            ScopedSelector::Class(a) | ScopedSelector::Id(a)
+                if hash.templated(&a.ident).is_some() =>
+            {
+                Some(Self::make_hashed_css(value, hash))
+            }
             // Class scoping is done with class composition
             ScopedSelector::Class(a) => {
                 cfg_if! {
                     if #[cfg(feature = "lepty-scoping")] {
                         Some(a.ident.to_string())
                     } else {
-                        Some(format!("{} {}", _hash.as_str(), a.ident))
+                        Some(format!("{} {}", hash.as_str(), a.ident))
                     }
                 }
             }
@@ -288,12 +455,17 @@ impl HashedSelector {
                     if #[cfg(feature = "lepty-scoping")] {
                         Some(a.ident.to_string())
                     } else {
-                        Some(format!("{}-{}", _hash.as_str(), a.ident))
+                        Some(format!("{}-{}", hash.as_str(), a.ident))
                     }
                 }
             }
             ScopedSelector::Tag(_) => None,
             ScopedSelector::Glob { .. } => None,
+            // Hashed name is what HTML-ish inline style will need
+            ScopedSelector::Keyframes(_)
+            | ScopedSelector::Container(_)
+            | ScopedSelector::CounterStyle(_)
+            | ScopedSelector::Property(_) => Some(Self::make_hashed_css(value, hash)),
         }
     }
 }
@@ -318,6 +490,14 @@ pub enum ScopedSelector {
         inner_span: Span,
         raw: String,
     },
+    /// `@keyframes` name
+    Keyframes(ArbitrarySelector),
+    /// `container-name` name
+    Container(ArbitrarySelector),
+    /// `@counter-style` name
+    CounterStyle(ArbitrarySelector),
+    /// `@property` name
+    Property(ArbitrarySelector),
 }
 
 impl ScopedSelector {
@@ -328,24 +508,92 @@ impl ScopedSelector {
             Self::Id(a) => a,
             Self::Tag(a) => a,
             Self::Glob { origin, .. } => origin,
+            Self::Keyframes(a) => a,
+            Self::Container(a) => a,
+            Self::CounterStyle(a) => a,
+            Self::Property(a) => a,
         }
     }
 
-    /// Generate rusty member ident based on selector type and CSS ident
-    pub fn gen_rusty_ident(&self) -> Option<syn::Ident> {
-        let arb = &self.as_arbitrary().ident;
-        let basic = apply_basic_rusty_member_gen_rules(arb);
+    /// Map the spans onto some other code, keeping the identifiers
+    pub fn map_spans(&mut self, map: impl Fn(&Span) -> Span) {
+        match self {
+            Self::Glob {
+                origin, inner_span, ..
+            } => {
+                origin.span = map(&origin.span);
+                *inner_span = map(inner_span);
+            }
+            Self::Class(a)
+            | Self::Id(a)
+            | Self::Tag(a)
+            | Self::Keyframes(a)
+            | Self::Container(a)
+            | Self::CounterStyle(a)
+            | Self::Property(a) => a.span = map(&a.span),
+        }
+    }
 
-        let ready = match self {
-            Self::Class(_) => Some(basic),
-            Self::Id(_) => Some(format!("the{basic}")),
-            Self::Tag(_) => Some(format!("any{basic}")),
-            Self::Glob { .. } => None,
-        };
+    /// Identifier which may be renamed as a whole, with naming template or mangling.
+    ///
+    /// Tagnames and globals are never renamed, so [None]
+    pub fn local(&self) -> Option<&str> {
+        match self {
+            Self::Tag(_) | Self::Glob { .. } => None,
+            Self::Property(a) => Some(a.ident.trim_start_matches("--")),
+            _ => Some(&self.as_arbitrary().ident),
+        }
+    }
 
-        ready.map(|rs| {
-            syn::parse_str::<syn::Ident>(&rs).expect("BUG: formed ident is not Rust Ident")
-        })
+    /// Selector or identifier the member is made of, the way it is written in CSS
+    pub fn source(&self) -> String {
+        let ident = &self.as_arbitrary().ident;
+        match self {
+            Self::Class(_) => format!(".{ident}"),
+            Self::Id(_) => format!("#{ident}"),
+            Self::Tag(_) | Self::Property(_) => ident.clone(),
+            Self::Glob { raw, .. } => format!(":global({raw})"),
+            Self::Keyframes(_) => format!("@keyframes {ident}"),
+            Self::Container(_) => format!("container-name: {ident}"),
+            Self::CounterStyle(_) => format!("@counter-style {ident}"),
+        }
+    }
+
+    /// Generate rusty member ident based on selector type and CSS ident, in the naming convention.
+    ///
+    /// Different selectors may get the same ident, see [unique_member_names]
+    pub fn gen_rusty_ident(&self, naming: &SabryNamingConfig) -> Option<syn::Ident> {
+        self.rusty_name(naming).map(|n| rusty_ident(&n))
+    }
+
+    /// Name of the [Self::gen_rusty_ident], which may be a keyword.
+    ///
+    /// Keywords which can not be raw identifiers, like `self`, are suffixed with `_` already,
+    /// so [unique_member_names] tells them apart from the names they turn into
+    pub fn rusty_name(&self, naming: &SabryNamingConfig) -> Option<String> {
+        let ident = &self.as_arbitrary().ident;
+        let prefix = match self {
+            Self::Class(_) => "",
+            Self::Id(_) => &naming.id_prefix,
+            Self::Tag(_) => &naming.tag_prefix,
+            Self::Glob { .. } => return None,
+            Self::Keyframes(_) => "keyframes",
+            Self::Container(_) => "container",
+            Self::CounterStyle(_) => "counter",
+            Self::Property(_) => "property",
+        };
+        // configured prefixes are glued as is, while the ones of the identifier kind are words of the name
+        let kind_prefixed = !matches!(self, Self::Class(_) | Self::Id(_) | Self::Tag(_));
+        let name = match naming.convention {
+            BehavNamingConvention::Camel if kind_prefixed => {
+                let name = member_name(naming, "", ident);
+                let mut chars = name.chars();
+                let first = chars.next().map(|c| c.to_ascii_uppercase());
+                format!("{prefix}{}{}", first.unwrap_or_default(), chars.as_str())
+            }
+            _ => member_name(naming, prefix, ident),
+        };
+        Some(unkeyword(name))
     }
 
     /// Construct this from glob complex selector
@@ -363,6 +611,20 @@ impl ScopedSelector {
         }
     }
 
+    /// Construct this from author-defined identifier
+    pub fn from_author_ident(i: &AuthorIdent) -> Self {
+        let arb = ArbitrarySelector {
+            ident: i.raw.to_string(),
+            span: i.span.clone(),
+        };
+        match i.kind {
+            AuthorIdentKind::Keyframes => Self::Keyframes(arb),
+            AuthorIdentKind::Container => Self::Container(arb),
+            AuthorIdentKind::CounterStyle => Self::CounterStyle(arb),
+            AuthorIdentKind::Property => Self::Property(arb),
+        }
+    }
+
     /// Construct this from class selector
     pub fn from_class(s: &raffia::ast::ClassSelector) -> Self {
         let lit = s
@@ -401,6 +663,127 @@ impl ScopedSelector {
     }
 }
 
+/// Rust identifier of the member name, raw one for keywords like `type`.
+///
+/// Keywords which can not be raw, like `self`, are expected to be suffixed already with [ScopedSelector::rusty_name]
+pub fn rusty_ident(name: &str) -> syn::Ident {
+    syn::parse_str::<syn::Ident>(name)
+        .or_else(|_| syn::parse_str(&format!("r#{name}")))
+        .expect("BUG: formed ident is not Rust Ident")
+}
+
+/// Name suffixed with `_` if it is a keyword which can not be raw identifier, like `self`
+fn unkeyword(name: String) -> String {
+    let valid = |n: &str| {
+        syn::parse_str::<syn::Ident>(n).is_ok()
+            || syn::parse_str::<syn::Ident>(&format!("r#{n}")).is_ok()
+    };
+
+    if valid(&name) {
+        name
+    } else if valid(&format!("{name}_")) {
+        format!("{name}_")
+    } else {
+        format!("_{name}_")
+    }
+}
+
+/// Member names by their sources, told apart where different sources make the same name.
+///
+/// Members are pairs of the source, like `.foo-bar`, and the name. Pairs of the same source are the same member.
+/// With [BehavNameCollision::Suffix] the colliding sources are sorted, and all but the first one are suffixed,
+/// so names do not depend on the order of the code. With [BehavNameCollision::Error] collisions are returned,
+/// as names along with their sources
+#[allow(clippy::type_complexity)]
+pub fn unique_member_names(
+    members: impl IntoIterator<Item = (String, String)>,
+    collision: BehavNameCollision,
+) -> Result<HashMap<String, String>, Vec<(String, Vec<String>)>> {
+    let mut sources: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (source, name) in members {
+        sources.entry(name).or_default().insert(source);
+    }
+
+    let colliding = sources
+        .iter()
+        .filter(|(_, s)| s.len() > 1)
+        .map(|(n, s)| (n.clone(), s.iter().cloned().collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    if matches!(collision, BehavNameCollision::Error) && !colliding.is_empty() {
+        return Err(colliding);
+    }
+
+    let mut taken = sources.keys().cloned().collect::<HashSet<_>>();
+    let mut names = HashMap::new();
+    for (name, sources) in sources {
+        let mut sources = sources.into_iter();
+        if let Some(first) = sources.next() {
+            names.insert(first, name.clone());
+        }
+        let mut n = 1;
+        for source in sources {
+            let suffixed = loop {
+                n += 1;
+                let suffixed = format!("{name}_{n}");
+                if taken.insert(suffixed.clone()) {
+                    break suffixed;
+                }
+            };
+            names.insert(source, suffixed);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Rust name of the member made of the prefix and the CSS identifier, in the naming convention
+pub fn member_name(naming: &SabryNamingConfig, prefix: &str, source: &str) -> String {
+    let name = match naming.convention {
+        BehavNamingConvention::Camel => {
+            format!("{prefix}{}", apply_basic_rusty_member_gen_rules(source))
+        }
+        BehavNamingConvention::Snake => name_words(prefix, source).join("_").to_lowercase(),
+        BehavNamingConvention::Screaming => name_words(prefix, source).join("_").to_uppercase(),
+        BehavNamingConvention::Verbatim => {
+            let escaped = source
+                .trim_start_matches('-')
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>();
+            match prefix {
+                "" => escaped,
+                _ => format!("{prefix}_{escaped}"),
+            }
+        }
+    };
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// Words of the prefix and the identifier, split at chars other than letters and digits and at the case change
+fn name_words<'s>(prefix: &'s str, source: &'s str) -> Vec<&'s str> {
+    let mut words = vec![];
+    for part in [prefix, source] {
+        for chunk in part.split(|c: char| !c.is_ascii_alphanumeric()) {
+            let mut start = 0;
+            let bytes = chunk.as_bytes();
+            for i in 1..bytes.len() {
+                if bytes[i - 1].is_ascii_lowercase() && bytes[i].is_ascii_uppercase() {
+                    words.push(&chunk[start..i]);
+                    start = i;
+                }
+            }
+            words.push(&chunk[start..]);
+        }
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
 pub fn apply_basic_rusty_member_gen_rules(source: &str) -> String {
     let omit_regex = Regex::new(r"(^\-)|(\-$)|[^a-zA-Z0-9\-\_]")
         .expect("BUG: can not build omition regex for rusty member generation");
@@ -435,15 +818,15 @@ pub fn apply_basic_rusty_member_gen_rules(source: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
 
     use cfg_if::cfg_if;
     use syn::Ident;
 
     use crate::{
-        config::SabryHashConfig,
-        scoper::{hash::ScopeHash, HashedScope},
-        syntax::ostrta::OneSyntaxToRuleThemAll,
+        config::{BehavNameCollision, BehavNamingConvention, SabryHashConfig, SabryNamingConfig},
+        scoper::{hash::ScopeHash, HashedScope, ScopeError},
+        syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode},
     };
 
     use super::ArbitraryScope;
@@ -482,13 +865,9 @@ mod test {
             }
         }
 
-        let scope = ArbitraryScope::from_source(
-            OneSyntaxToRuleThemAll::Scss,
-            syn::parse_str("scope1").unwrap(),
-            code,
-        )
-        .unwrap();
-        let scope = HashedScope::new(hash, scope).unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
         let scope_selector_htmls = scope
             .hashed_selectors
             .iter()
@@ -503,6 +882,360 @@ mod test {
         assert_eq!(expect_selector_htmls, scope_selector_htmls);
     }
 
+    #[test]
+    fn scope_hash_at_rules() {
+        let code = "@media (max-width: 600px) {.card{color:red;}} @supports (display: grid) {@layer base {.grid{display:grid;}}}";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = "@media (max-width: 600px) {.F2kf8nMs.card{color:red;}} @supports (display: grid) {@layer base {.F2kf8nMs.grid{display:grid;}}}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+
+        assert_eq!(expect_code, scope.hashed_code);
+    }
+
+    #[test]
+    fn scope_hash_pseudo_args() {
+        let code = ".list:not(.active){color:red;} .card:has(.icon){color:red;} :is(.a, .b){color:red;} .c:not(:global(.d)){color:red;}";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = ".F2kf8nMs.list:not(.F2kf8nMs.active){color:red;} .F2kf8nMs.card:has(.F2kf8nMs.icon){color:red;} :is(.F2kf8nMs.a, .F2kf8nMs.b){color:red;} .F2kf8nMs.c:not(.d){color:red;}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| hs.sel.gen_rusty_ident(&SabryNamingConfig::default()))
+            .map(|i| i.to_string())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from(["list", "active", "card", "icon", "a", "b", "c"].map(String::from)),
+            scope_members
+        );
+    }
+
+    #[test]
+    fn scope_hash_author_idents() {
+        let code = "@keyframes fade{from{opacity:0;}} .a{animation:fade 1s, global(spin) 2s; container:side / size; list-style:thumbs; --x:1px; width:var(--x);} @container side (width > 1px){.b{content:counter(c, thumbs);}} @counter-style thumbs{system:cyclic;} @property --x{syntax:'<length>';} :global{@keyframes glob{from{opacity:0;}}} .c{animation-name:glob; -webkit-animation:fade 1s; view-animation:fade;}";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+
+        let expect_code = "@keyframes F2kf8nMs-fade{from{opacity:0;}} .F2kf8nMs.a{animation:F2kf8nMs-fade 1s, spin 2s; container:F2kf8nMs-side / size; list-style:F2kf8nMs-thumbs; --F2kf8nMs-x:1px; width:var(--F2kf8nMs-x);} @container F2kf8nMs-side (width > 1px){.F2kf8nMs.b{content:counter(c, F2kf8nMs-thumbs);}} @counter-style F2kf8nMs-thumbs{system:cyclic;} @property --F2kf8nMs-x{syntax:'<length>';} :global{@keyframes glob{from{opacity:0;}}} .F2kf8nMs.c{animation-name:glob; -webkit-animation:F2kf8nMs-fade 1s; view-animation:fade;}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| {
+                hs.sel
+                    .gen_rusty_ident(&SabryNamingConfig::default())
+                    .and_then(|i| hs.html_ident.clone().map(|h| (i.to_string(), h)))
+            })
+            .filter(|(i, _)| !["a", "b", "c"].contains(&i.as_str()))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from([
+                ("keyframesFade".to_string(), "F2kf8nMs-fade".to_string()),
+                ("containerSide".to_string(), "F2kf8nMs-side".to_string()),
+                ("counterThumbs".to_string(), "F2kf8nMs-thumbs".to_string()),
+                ("propertyX".to_string(), "--F2kf8nMs-x".to_string()),
+            ]),
+            scope_members
+        );
+    }
+
+    #[test]
+    fn scope_hash_custom_properties() {
+        let code = ".a{--accent:red; --shade:var(--ext); color:var(--accent, var(--ext));} :global{:root{--ext:blue;}} .b{background:var(--accent);}";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+
+        let expect_code = ".F2kf8nMs.a{--F2kf8nMs-accent:red; --F2kf8nMs-shade:var(--ext); color:var(--F2kf8nMs-accent, var(--ext));} :global{:root{--ext:blue;}} .F2kf8nMs.b{background:var(--F2kf8nMs-accent);}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let config = SabryHashConfig {
+            custom_properties: true,
+            ..Default::default()
+        };
+        let scope = HashedScope::new(hash, scope, &config).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| hs.sel.gen_rusty_ident(&SabryNamingConfig::default()))
+            .map(|i| i.to_string())
+            .filter(|i| i.starts_with("property"))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from(["propertyAccent", "propertyShade"].map(String::from)),
+            scope_members
+        );
+    }
+
+    #[test]
+    fn scope_hash_template() {
+        let code = ".btn{color:red; &:hover{color:black;}} #main{animation:fade 1s;} @keyframes fade{from{opacity:0;}}";
+        let hash =
+            ScopeHash::test_init_templated("F2kf8nMs".into(), "scope1", "[scope]__[local]--[hash]");
+        let expect_code = ".scope1__btn--F2kf8nMs{color:red; &:hover{color:black;}} #scope1__main--F2kf8nMs{animation:scope1__fade--F2kf8nMs 1s;} @keyframes scope1__fade--F2kf8nMs{from{opacity:0;}}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| {
+                hs.sel
+                    .gen_rusty_ident(&SabryNamingConfig::default())
+                    .and_then(|i| hs.html_ident.clone().map(|h| (i.to_string(), h)))
+            })
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from([
+                ("btn".to_string(), "scope1__btn--F2kf8nMs".to_string()),
+                ("themain".to_string(), "scope1__main--F2kf8nMs".to_string()),
+                (
+                    "keyframesFade".to_string(),
+                    "scope1__fade--F2kf8nMs".to_string()
+                ),
+            ]),
+            scope_members
+        );
+    }
+
+    #[test]
+    fn scope_hash_template_rejects_suffixed_nesting() {
+        let code = ".btn{color:red; &-dark{color:black;}}";
+        let hash =
+            ScopeHash::test_init_templated("F2kf8nMs".into(), "scope1", "[scope]__[local]--[hash]");
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let Err(ScopeError::RenamedNesting(source, span)) =
+            HashedScope::new(hash, scope, &SabryHashConfig::default())
+        else {
+            panic!("suffixed nesting is scoped under the template");
+        };
+
+        assert_eq!("&-dark", source);
+        assert_eq!(16..22, span.start..span.end);
+    }
+
+    #[test]
+    fn scope_hash_sass_codegen() {
+        let code = "
+.cls1
+  color: red
+  &-dark
+    color: black
+  .cls2
+    color: green
+.cls3
+  color: black";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = "
+.F2kf8nMs.cls1
+  color: red
+  &-dark
+    color: black
+  .F2kf8nMs.cls2
+    color: green
+.F2kf8nMs.cls3
+  color: black";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| hs.sel.gen_rusty_ident(&SabryNamingConfig::default()))
+            .map(|i| i.to_string())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(code, scope.original_scope.adapter().source());
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from(["cls1", "cls2", "cls3"].map(String::from)),
+            scope_members
+        );
+    }
+
+    #[test]
+    fn scope_hash_sass_at_rules() {
+        let code = "
+@media (max-width: 600px)
+  .card
+    color: red
+@supports (display: grid)
+  @layer base
+    .grid
+      display: grid
+.list:not(.active)
+  color: red
+.c:not(:global(.d))
+  color: red";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = "
+@media (max-width: 600px)
+  .F2kf8nMs.card
+    color: red
+@supports (display: grid)
+  @layer base
+    .F2kf8nMs.grid
+      display: grid
+.F2kf8nMs.list:not(.F2kf8nMs.active)
+  color: red
+.F2kf8nMs.c:not(.d)
+  color: red";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+
+        assert_eq!(expect_code, scope.hashed_code);
+    }
+
+    #[test]
+    fn scope_hash_sass_author_idents() {
+        let code = "
+@keyframes fade
+  from
+    opacity: 0
+  to
+    opacity: 1
+.a
+  animation: fade 1s, global(spin) 2s
+  container: side / size
+  --accent: red
+  color: var(--accent)
+@container side (width > 1px)
+  .b
+    color: red
+@property --accent
+  syntax: '<color>'";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = "
+@keyframes F2kf8nMs-fade
+  from
+    opacity: 0
+  to
+    opacity: 1
+.F2kf8nMs.a
+  animation: F2kf8nMs-fade 1s, spin 2s
+  container: F2kf8nMs-side / size
+  --F2kf8nMs-accent: red
+  color: var(--F2kf8nMs-accent)
+@container F2kf8nMs-side (width > 1px)
+  .F2kf8nMs.b
+    color: red
+@property --F2kf8nMs-accent
+  syntax: '<color>'";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let config = SabryHashConfig {
+            custom_properties: true,
+            ..Default::default()
+        };
+        let scope = HashedScope::new(hash, scope, &config).unwrap();
+
+        assert_eq!(expect_code, scope.hashed_code);
+    }
+
+    #[test]
+    fn scope_hash_sass_template() {
+        let code = "
+.btn
+  color: red
+  &:hover
+    color: black
+.main
+  animation: fade 1s
+@keyframes fade
+  from
+    opacity: 0";
+        let hash =
+            ScopeHash::test_init_templated("F2kf8nMs".into(), "scope1", "[scope]__[local]--[hash]");
+        let expect_code = "
+.scope1__btn--F2kf8nMs
+  color: red
+  &:hover
+    color: black
+.scope1__main--F2kf8nMs
+  animation: scope1__fade--F2kf8nMs 1s
+@keyframes scope1__fade--F2kf8nMs
+  from
+    opacity: 0";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+
+        assert_eq!(expect_code, scope.hashed_code);
+    }
+
+    #[test]
+    fn scope_hash_sass_structure() {
+        // deep dedents, control flow, shorthands, comments, continued selectors, CRLF and tabs
+        let code = "\r
+=dark\r
+\t&-dark\r
+\t\tcolor: black // .not-hashed\r
+.card,\r
+.tile\r
+\t+dark\r
+\t/* .not-hashed\r
+\t   either\r
+\t@if $a == 1\r
+\t\t.x\r
+\t\t\t.y\r
+\t\t\t\tcolor: red\r
+\t@else\r
+\t\t.z\r
+\t\t\tcolor: blue\r
+\t@each $c in a, b\r
+\t\t.e-#{$c}\r
+\t\t\tcolor: $c\r
+.last\r
+\tcolor: red";
+        let hash = ScopeHash::test_init("F2kf8nMs".into());
+        let expect_code = code
+            .replace(".card", ".F2kf8nMs.card")
+            .replace(".tile", ".F2kf8nMs.tile")
+            .replace(".x", ".F2kf8nMs.x")
+            .replace(".y", ".F2kf8nMs.y")
+            .replace(".z", ".F2kf8nMs.z")
+            .replace(".last", ".F2kf8nMs.last");
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style).unwrap();
+        let scope = HashedScope::new(hash, scope, &SabryHashConfig::default()).unwrap();
+        let scope_members = scope
+            .hashed_selectors
+            .iter()
+            .filter_map(|hs| hs.sel.gen_rusty_ident(&SabryNamingConfig::default()))
+            .map(|i| i.to_string())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_code, scope.hashed_code);
+        assert_eq!(
+            HashSet::from(["card", "tile", "x", "y", "z", "last"].map(String::from)),
+            scope_members
+        );
+    }
+
     #[test]
     fn rusty_idents() {
         let code = "
@@ -530,22 +1263,125 @@ mod test {
         ]);
 
         let hash_cfg = SabryHashConfig::default();
-        let scope = ArbitraryScope::from_source(
-            OneSyntaxToRuleThemAll::Scss,
-            syn::parse_str::<Ident>("scope2oiej").unwrap(),
-            code,
-        )
-        .unwrap()
-        .hashed(&hash_cfg)
-        .unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope =
+            ArbitraryScope::from_source(syn::parse_str::<Ident>("scope2oiej").unwrap(), &style)
+                .unwrap()
+                .hashed(&hash_cfg)
+                .unwrap();
 
         let sels = scope
             .hashed_selectors
             .iter()
-            .map(|s| s.sel.gen_rusty_ident())
+            .map(|s| s.sel.gen_rusty_ident(&SabryNamingConfig::default()))
             .flat_map(|i| i.map(|is| is.to_string()))
             .collect::<HashSet<_>>();
 
         assert_eq!(expect_ident_names, sels);
     }
+
+    #[test]
+    fn member_naming_conventions() {
+        let code = ".cls-1{color:red;} #id-1{color:green;} .-txt-of{color:blue;} .-c_ls3#4id{color:black;} .fooBar{color:red;}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style)
+            .unwrap()
+            .hashed(&SabryHashConfig::default())
+            .unwrap();
+        let names = |naming: &SabryNamingConfig| {
+            scope
+                .hashed_selectors
+                .iter()
+                .filter_map(|s| s.sel.gen_rusty_ident(naming))
+                .map(|i| i.to_string())
+                .collect::<HashSet<_>>()
+        };
+
+        let snake = SabryNamingConfig {
+            convention: BehavNamingConvention::Snake,
+            ..Default::default()
+        };
+        assert_eq!(
+            HashSet::from(
+                ["cls_1", "the_id_1", "txt_of", "c_ls3", "the_4id", "foo_bar"].map(String::from)
+            ),
+            names(&snake)
+        );
+
+        let screaming = SabryNamingConfig {
+            convention: BehavNamingConvention::Screaming,
+            id_prefix: "id".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            HashSet::from(
+                ["CLS_1", "ID_ID_1", "TXT_OF", "C_LS3", "ID_4ID", "FOO_BAR"].map(String::from)
+            ),
+            names(&screaming)
+        );
+
+        let verbatim = SabryNamingConfig {
+            convention: BehavNamingConvention::Verbatim,
+            id_prefix: "".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            HashSet::from(["cls_1", "id_1", "txt_of", "c_ls3", "_4id", "fooBar"].map(String::from)),
+            names(&verbatim)
+        );
+    }
+
+    #[test]
+    fn member_names_unique() {
+        use super::{rusty_ident, unique_member_names};
+
+        let code = ".foo-bar{color:red;} .type{color:red;} .fooBar{color:red;} .fooBar_2{color:red;} .self{color:red;} .self_{color:red;} .foo-bar{color:blue;}";
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, code);
+        let scope = ArbitraryScope::from_source(syn::parse_str("scope1").unwrap(), &style)
+            .unwrap()
+            .hashed(&SabryHashConfig::default())
+            .unwrap();
+        let naming = SabryNamingConfig::default();
+        let members = || {
+            scope
+                .hashed_selectors
+                .iter()
+                .filter_map(|hs| Some((hs.sel.source(), hs.sel.rusty_name(&naming)?)))
+        };
+
+        let names = unique_member_names(members(), BehavNameCollision::Suffix).unwrap();
+        let idents = names
+            .iter()
+            .map(|(source, name)| (source.as_str(), rusty_ident(name).to_string()))
+            .collect::<HashMap<_, _>>();
+        // sorted sources, so `.foo-bar` goes first and `fooBar_2` is taken
+        assert_eq!(
+            HashMap::from([
+                (".foo-bar", "fooBar".to_string()),
+                (".fooBar", "fooBar_3".to_string()),
+                (".fooBar_2", "fooBar_2".to_string()),
+                (".type", "r#type".to_string()),
+                (".self", "self_".to_string()),
+                (".self_", "self__2".to_string()),
+            ]),
+            idents
+        );
+
+        let collisions = unique_member_names(members(), BehavNameCollision::Error).unwrap_err();
+        assert_eq!(
+            vec![
+                (
+                    "fooBar".to_string(),
+                    vec![".foo-bar".to_string(), ".fooBar".to_string()]
+                ),
+                (
+                    "self_".to_string(),
+                    vec![".self".to_string(), ".self_".to_string()]
+                )
+            ],
+            collisions
+        );
+    }
 }
@@ -0,0 +1,123 @@
+use std::{
+    env,
+    path::{Component, Path},
+    process::Command,
+};
+
+use crate::config::manifest;
+
+/// Where the scope is defined: crate name and module path.
+///
+/// Module path is derived from the source file relative to the crate root,
+/// so both `styly!` macro and the builder come to the same one:
+/// `src/components/button.rs` is `components::button`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ScopeOrigin {
+    pub krate: Option<String>,
+    pub module: Option<String>,
+}
+
+impl ScopeOrigin {
+    /// Origin of the scope defined in the given source file of the crate being built
+    pub fn new(file: Option<&Path>) -> Self {
+        let krate = env::var("CARGO_PKG_NAME").ok().map(|n| n.replace('-', "_"));
+        let module = file.and_then(Self::module_of);
+
+        Self { krate, module }
+    }
+
+    /// Whether proc macros built with the current toolchain know their source file,
+    /// so `styly!` could qualify the scope with module path.
+    ///
+    /// The file is known to stable rust since 1.88. The version is asked from `RUSTC` cargo gives to build scripts
+    pub fn macros_know_file() -> bool {
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+
+        Command::new(rustc)
+            .arg("--version")
+            .output()
+            .ok()
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+            .and_then(|v| {
+                // rustc 1.88.0 (6b00bc388 2025-06-23)
+                let mut version = v.split_whitespace().nth(1)?.split('.');
+                let major = version.next()?.parse::<u32>().ok()?;
+                let minor = version.next()?.parse::<u32>().ok()?;
+                Some((major, minor) >= (1, 88))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Fully qualified scope name, like `crate::components::button::scope`
+    pub fn qualify(&self, name: &syn::Ident) -> String {
+        let name = name.to_string();
+
+        [self.krate.as_deref(), self.module.as_deref(), Some(&name)]
+            .into_iter()
+            .flatten()
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    /// Name for the CSS chunk file of the scope, without extension
+    pub fn chunk(&self, name: &syn::Ident) -> String {
+        self.qualify(name).replace("::", ".")
+    }
+
+    /// Module path from the source file path
+    fn module_of(file: &Path) -> Option<String> {
+        let file = manifest::crate_relative(file);
+
+        let mut parts = file
+            .with_extension("")
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(p) => Some(p.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if parts.first().is_some_and(|p| p == "src") {
+            parts.remove(0);
+        }
+        if parts
+            .last()
+            .is_some_and(|p| p == "mod" || p == "lib" || p == "main")
+        {
+            parts.pop();
+        }
+
+        Some(parts.join("::"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, path::Path};
+
+    use super::ScopeOrigin;
+
+    #[test]
+    fn origin_module_path() {
+        let name = syn::parse_str("scope").unwrap();
+        let root = env::var("CARGO_MANIFEST_DIR").unwrap();
+
+        for (file, qualified) in [
+            (
+                "src/scoper/origin.rs",
+                "sabry_intrnl::scoper::origin::scope",
+            ),
+            ("src/scoper/mod.rs", "sabry_intrnl::scoper::scope"),
+            ("src/lib.rs", "sabry_intrnl::scope"),
+        ] {
+            let relative = ScopeOrigin::new(Some(Path::new(file)));
+            let absolute = ScopeOrigin::new(Some(&Path::new(&root).join(file)));
+
+            assert_eq!(qualified, relative.qualify(&name));
+            assert_eq!(relative, absolute);
+        }
+
+        assert_eq!("scope", ScopeOrigin::default().qualify(&name));
+    }
+}
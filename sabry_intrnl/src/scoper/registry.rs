@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, fs, io, path::Path, sync::Arc};
+
+use crate::config::manifest::{self, HASH_REGISTRY_ENV};
+
+/// On-disk registry of scope hashes.
+///
+/// Maps fully qualified scope name onto the salt it is hashed with, and the resulting hash.
+/// Written by the builder, so `styly!` macros are able to read it and agree on the final hash
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRegistry(BTreeMap<String, (usize, String)>);
+
+impl HashRegistry {
+    /// Salt the scope is hashed with. Unknown scopes are not salted
+    pub fn salt_of(&self, scope: &str) -> usize {
+        self.0.get(scope).map(|(salt, _)| *salt).unwrap_or_default()
+    }
+
+    /// Registered scope with the same hash, other than the given one
+    pub fn colliding(&self, scope: &str, hash: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(s, (_, h))| *s != scope && h == hash)
+            .map(|(s, _)| s.as_str())
+    }
+
+    /// Pairs of scopes of this and another registry having the same hash
+    pub fn collisions_with<'r>(&'r self, other: &'r Self) -> Vec<(&'r str, &'r str)> {
+        self.0
+            .iter()
+            .filter_map(|(s, (_, h))| other.colliding(s, h).map(|o| (s.as_str(), o)))
+            .collect()
+    }
+
+    pub fn insert(&mut self, scope: String, salt: usize, hash: String) {
+        self.0.insert(scope, (salt, hash));
+    }
+
+    /// Registry `buildy` has written for the crate being built, as told with [HASH_REGISTRY_ENV].
+    ///
+    /// Read once until it is changed, see [manifest::built_file]
+    pub fn built() -> io::Result<Option<Arc<Self>>> {
+        manifest::built_file(HASH_REGISTRY_ENV, Self::read)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let registry = fs::read_to_string(path)?
+            .lines()
+            .filter_map(|l| {
+                let mut parts = l.split_whitespace();
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(s), Some(salt), Some(h)) => salt
+                        .parse()
+                        .ok()
+                        .map(|salt| (s.to_string(), (salt, h.to_string()))),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Ok(Self(registry))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let registry = self
+            .0
+            .iter()
+            .map(|(s, (salt, h))| format!("{s} {salt} {h}\n"))
+            .collect::<String>();
+
+        fs::write(path, registry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HashRegistry;
+
+    #[test]
+    fn registry_roundtrip() {
+        let mut registry = HashRegistry::default();
+        registry.insert("crate1::a::scope".into(), 0, "Hsh1".into());
+        registry.insert("crate1::b::scope".into(), 2, "Hsh2".into());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hashes");
+        registry.write(&path).unwrap();
+        let read = HashRegistry::read(&path).unwrap();
+
+        assert_eq!(registry, read);
+        assert_eq!(2, read.salt_of("crate1::b::scope"));
+        assert_eq!(0, read.salt_of("crate1::c::scope"));
+        assert_eq!(
+            Some("crate1::b::scope"),
+            read.colliding("crate1::c::scope", "Hsh2")
+        );
+        assert_eq!(None, read.colliding("crate1::b::scope", "Hsh2"));
+
+        let mut other = HashRegistry::default();
+        other.insert("crate2::scope".into(), 0, "Hsh1".into());
+        assert_eq!(
+            vec![("crate2::scope", "crate1::a::scope")],
+            other.collisions_with(&read)
+        );
+    }
+}
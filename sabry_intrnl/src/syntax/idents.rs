@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+
+use raffia::{
+    ast::{
+        AtRulePrelude, ComponentValue, Declaration, Function, InterpolableIdent, KeyframesName,
+        QualifiedRule, Statement,
+    },
+    token::Token,
+    Span,
+};
+
+use super::{Selectors, StylesheetAdapter};
+
+/// Kind of author-defined identifier, which is not a selector, yet may be scoped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthorIdentKind {
+    /// `@keyframes name`, referenced from `animation` and `animation-name`
+    Keyframes,
+    /// `container-name: name`, referenced from `@container name`
+    Container,
+    /// `@counter-style name`, referenced from `list-style`, `counter()` and alike
+    CounterStyle,
+    /// `@property --name`, referenced from `var(--name)` and `--name: value`
+    Property,
+}
+
+/// Occurrence of author-defined identifier in the stylesheet:
+/// either its definition or a reference to it
+#[derive(Debug, Clone)]
+pub struct AuthorIdent<'s> {
+    pub kind: AuthorIdentKind,
+    pub raw: &'s str,
+    pub span: Span,
+}
+
+impl<'s> StylesheetAdapter<'s> {
+    /// Author-defined identifiers declared by this stylesheet, see [Selectors::author_ident_definitions]
+    pub fn author_ident_definitions(&self, custom_properties: bool) -> Vec<AuthorIdent<'s>> {
+        self.gather_selectors()
+            .author_ident_definitions(custom_properties)
+    }
+
+    /// Every occurrence of author-defined identifiers, see [Selectors::author_idents]
+    pub fn author_idents(&self, custom_properties: bool) -> Vec<AuthorIdent<'s>> {
+        self.gather_selectors().author_idents(custom_properties)
+    }
+
+    /// special value case searching for global(whatever) functions in declaration values
+    pub fn glob_modified_values(&self) -> Vec<Function<'s>> {
+        self.gather_selectors().glob_modified_values()
+    }
+
+    /// Whether the function is the `global(whatever)` scoping opt-out
+    pub fn is_glob_modifier_fn(f: &Function<'s>) -> bool {
+        f.name
+            .as_ident()
+            .and_then(|i| i.as_literal())
+            .is_some_and(|i| i.raw == "global")
+            && !f.args.is_empty()
+    }
+
+    /// Whether the rule is the bare `:global { }` block
+    pub fn is_glob_block(q: &QualifiedRule<'s>) -> bool {
+        let [complex] = q.selector.selectors.as_slice() else {
+            return false;
+        };
+        let [compound] = complex.children.as_slice() else {
+            return false;
+        };
+        let Some(compound) = compound.as_compound_selector() else {
+            return false;
+        };
+        let [simple] = compound.children.as_slice() else {
+            return false;
+        };
+
+        simple
+            .as_pseudo_class()
+            .is_some_and(|cps| cps.arg.is_none() && Self::is_glob_modifier(cps))
+    }
+
+    fn definitions_of(s: &Statement<'s>, custom_properties: bool) -> Vec<AuthorIdent<'s>> {
+        match s {
+            Statement::AtRule(a) => {
+                let def = match &a.prelude {
+                    Some(AtRulePrelude::Keyframes(KeyframesName::Ident(
+                        InterpolableIdent::Literal(i),
+                    ))) => Some((AuthorIdentKind::Keyframes, i)),
+                    Some(AtRulePrelude::CounterStyle(InterpolableIdent::Literal(i))) => {
+                        Some((AuthorIdentKind::CounterStyle, i))
+                    }
+                    Some(AtRulePrelude::Property(InterpolableIdent::Literal(i))) => {
+                        Some((AuthorIdentKind::Property, i))
+                    }
+                    _ => None,
+                };
+
+                def.map(|(kind, i)| AuthorIdent {
+                    kind,
+                    raw: i.raw,
+                    span: i.span.clone(),
+                })
+                .into_iter()
+                .collect()
+            }
+            Statement::Declaration(d) => match Self::declaration_name(d) {
+                Some("container-name") => {
+                    Self::value_idents(&d.value, AuthorIdentKind::Container, |_, _| true)
+                }
+                // container: <name> / <type>
+                Some("container") => {
+                    let names = d
+                        .value
+                        .iter()
+                        .take_while(|v| !v.as_delimiter().is_some_and(|d| d.kind.is_solidus()))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    Self::value_idents(&names, AuthorIdentKind::Container, |_, _| true)
+                }
+                Some(name) if custom_properties && name.starts_with("--") => d
+                    .name
+                    .as_literal()
+                    .map(|i| AuthorIdent {
+                        kind: AuthorIdentKind::Property,
+                        raw: i.raw,
+                        span: i.span.clone(),
+                    })
+                    .into_iter()
+                    .collect(),
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    fn references_of(s: &Statement<'s>) -> Vec<AuthorIdent<'s>> {
+        match s {
+            Statement::AtRule(a) => match &a.prelude {
+                Some(AtRulePrelude::Container(c)) => c
+                    .name
+                    .as_ref()
+                    .and_then(|n| n.as_literal())
+                    .map(|i| AuthorIdent {
+                        kind: AuthorIdentKind::Container,
+                        raw: i.raw,
+                        span: i.span.clone(),
+                    })
+                    .into_iter()
+                    .collect(),
+                _ => vec![],
+            },
+            Statement::Declaration(d) => {
+                let name = Self::declaration_name(d).unwrap_or_default();
+
+                let mut refs = vec![];
+                // --name: value
+                if name.starts_with("--") {
+                    if let Some(i) = d.name.as_literal() {
+                        refs.push(AuthorIdent {
+                            kind: AuthorIdentKind::Property,
+                            raw: i.raw,
+                            span: i.span.clone(),
+                        });
+                    }
+                }
+                if matches!(Self::unvendored(name), "animation" | "animation-name") {
+                    refs.extend(Self::value_idents(
+                        &d.value,
+                        AuthorIdentKind::Keyframes,
+                        |f, _| f.is_none(),
+                    ));
+                }
+                if matches!(
+                    name,
+                    "list-style" | "list-style-type" | "system" | "fallback"
+                ) {
+                    refs.extend(Self::value_idents(
+                        &d.value,
+                        AuthorIdentKind::CounterStyle,
+                        |f, _| f.is_none(),
+                    ));
+                }
+                refs.extend(Self::value_idents(
+                    &d.value,
+                    AuthorIdentKind::CounterStyle,
+                    |f, _| matches!(f, Some("counter" | "counters")),
+                ));
+                // var(--name), as well as --name in `transition-property` and alike
+                refs.extend(Self::value_idents(
+                    &d.value,
+                    AuthorIdentKind::Property,
+                    |_, raw| raw.starts_with("--"),
+                ));
+
+                refs
+            }
+            _ => vec![],
+        }
+    }
+
+    fn declaration_name<'d>(d: &'d Declaration<'s>) -> Option<&'d str> {
+        d.name.as_literal().map(|i| i.raw)
+    }
+
+    /// Property name without the vendor prefix, like `animation` of `-webkit-animation`
+    fn unvendored(name: &str) -> &str {
+        match name.strip_prefix('-').filter(|n| !n.starts_with('-')) {
+            Some(vendored) => vendored.split_once('-').map_or(name, |(_, n)| n),
+            None => name,
+        }
+    }
+
+    /// Collect identifiers from declaration values,
+    /// filtered by the function they are the argument of and the identifier itself
+    fn value_idents<F>(
+        values: &[ComponentValue<'s>],
+        kind: AuthorIdentKind,
+        filter: F,
+    ) -> Vec<AuthorIdent<'s>>
+    where
+        F: Fn(Option<&str>, &str) -> bool,
+    {
+        let mut idents = vec![];
+        Self::walk_values(
+            values,
+            None,
+            &mut |function, raw, span| {
+                if filter(function, raw) {
+                    idents.push(AuthorIdent {
+                        kind,
+                        raw,
+                        span: span.clone(),
+                    })
+                }
+            },
+            &mut |_| {},
+        );
+        idents
+    }
+
+    /// Walk the declaration values recursively, calling `on_ident` for every literal identifier,
+    /// along with the name of the function it is an argument of, and `on_glob` for every
+    /// `global(whatever)` function, which content is not walked
+    fn walk_values(
+        values: &[ComponentValue<'s>],
+        function: Option<&str>,
+        on_ident: &mut dyn FnMut(Option<&str>, &'s str, &Span),
+        on_glob: &mut dyn FnMut(&Function<'s>),
+    ) {
+        for value in values {
+            match value {
+                ComponentValue::InterpolableIdent(InterpolableIdent::Literal(i)) => {
+                    on_ident(function, i.raw, &i.span)
+                }
+                // custom properties values are not parsed, so here are raw tokens
+                ComponentValue::TokenWithSpan(t) => {
+                    if let Token::Ident(i) = &t.token {
+                        on_ident(function, i.raw, &t.span)
+                    }
+                }
+                ComponentValue::Function(f) if Self::is_glob_modifier_fn(f) => on_glob(f),
+                ComponentValue::Function(f) => {
+                    let name = f
+                        .name
+                        .as_ident()
+                        .and_then(|i| i.as_literal())
+                        .map(|i| i.raw);
+                    Self::walk_values(&f.args, name, on_ident, on_glob)
+                }
+                ComponentValue::SassList(l) => {
+                    Self::walk_values(&l.elements, function, on_ident, on_glob)
+                }
+                ComponentValue::SassParenthesizedExpression(p) => Self::walk_values(
+                    std::slice::from_ref(p.expr.as_ref()),
+                    function,
+                    on_ident,
+                    on_glob,
+                ),
+                ComponentValue::SassBinaryExpression(b) => Self::walk_values(
+                    &[b.left.as_ref().clone(), b.right.as_ref().clone()],
+                    function,
+                    on_ident,
+                    on_glob,
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'s> Selectors<'_, 's> {
+    /// Author-defined identifiers declared by the stylesheet.
+    ///
+    /// With `custom_properties` every `--name: value` declaration is treated as [AuthorIdentKind::Property]
+    /// definition, not only `@property --name` ones.
+    ///
+    /// Those declared inside of `:global { }` block are not included
+    pub fn author_ident_definitions(&self, custom_properties: bool) -> Vec<AuthorIdent<'s>> {
+        self.statements
+            .iter()
+            .filter(|(_, global)| !global)
+            .flat_map(|(s, _)| StylesheetAdapter::definitions_of(s, custom_properties))
+            .collect()
+    }
+
+    /// Every occurrence (definitions and references) of author-defined identifiers,
+    /// declared by the stylesheet.
+    ///
+    /// References to identifiers declared elsewhere are not included,
+    /// as well as ones wrapped into `global(name)`
+    pub fn author_idents(&self, custom_properties: bool) -> Vec<AuthorIdent<'s>> {
+        let defined = self
+            .author_ident_definitions(custom_properties)
+            .iter()
+            .map(|d| (d.kind, d.raw))
+            .collect::<HashSet<_>>();
+
+        self.statements
+            .iter()
+            .flat_map(|(s, _)| {
+                StylesheetAdapter::definitions_of(s, false)
+                    .into_iter()
+                    .chain(StylesheetAdapter::references_of(s))
+            })
+            .filter(|i| defined.contains(&(i.kind, i.raw)))
+            .collect()
+    }
+
+    /// `global(whatever)` functions in declaration values
+    pub fn glob_modified_values(&self) -> Vec<Function<'s>> {
+        let mut globs = vec![];
+        for d in self
+            .statements
+            .iter()
+            .filter_map(|(s, _)| s.as_declaration())
+        {
+            StylesheetAdapter::walk_values(&d.value, None, &mut |_, _, _| {}, &mut |f| {
+                globs.push(f.clone())
+            });
+        }
+        globs
+    }
+}
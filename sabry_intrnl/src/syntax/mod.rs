@@ -1,126 +1,280 @@
+use std::borrow::Cow;
+
 use ostrta::OneSyntaxToRuleThemAll;
 use raffia::{
     ast::{
-        ClassSelector, CompoundSelector, IdSelector, NestingSelector, PseudoClassSelector,
-        SimpleSelector, Statement, Stylesheet, TypeSelector,
+        ClassSelector, ComplexSelector, CompoundSelector, IdSelector, NestingSelector,
+        PseudoClassSelector, PseudoClassSelectorArgKind, SimpleBlock, SimpleSelector, Statement,
+        Stylesheet, TypeSelector,
     },
-    ParserBuilder,
+    ParserBuilder, Span,
 };
+use sass::SassAsScss;
 
+pub mod idents;
 pub mod ostrta;
+pub mod sass;
+
+/// Style code of known syntax, prepared for raffia to parse.
+///
+/// Indented SASS is parsed as the SCSS it is rewritten into, see [SassAsScss],
+/// so the spans of the syntax tree are mapped back with [StyleCode::original_span]
+pub struct StyleCode<'s> {
+    pub syntax: OneSyntaxToRuleThemAll,
+    pub code: Cow<'s, str>,
+    sass: Option<SassAsScss>,
+}
+
+impl<'s> StyleCode<'s> {
+    pub fn new(syntax: OneSyntaxToRuleThemAll, code: impl Into<Cow<'s, str>>) -> Self {
+        let code = code.into();
+        let sass = matches!(syntax, OneSyntaxToRuleThemAll::Sass).then(|| SassAsScss::new(&code));
+
+        Self { syntax, code, sass }
+    }
+
+    /// Code raffia parses, along with the syntax it is parsed with
+    fn parsed(&self) -> (&str, OneSyntaxToRuleThemAll) {
+        match &self.sass {
+            Some(sass) => (sass.code(), OneSyntaxToRuleThemAll::Scss),
+            None => (&self.code, self.syntax),
+        }
+    }
+
+    /// Span in the code of the span in the parsed one
+    pub fn original_span(&self, span: &Span) -> Span {
+        match &self.sass {
+            Some(sass) => sass.original_span(span),
+            None => span.clone(),
+        }
+    }
+}
 
 /// Convenience wrapper for [Stylesheet]
 pub struct StylesheetAdapter<'s> {
     pub syntax: OneSyntaxToRuleThemAll,
-    source: &'s str,
+    code: &'s StyleCode<'s>,
     stylesheet: Stylesheet<'s>,
 }
 
 impl<'s> StylesheetAdapter<'s> {
-    pub fn new(
-        source: &'s str,
-        syntax: OneSyntaxToRuleThemAll,
-    ) -> Result<Self, raffia::error::Error> {
+    /// Parse the code, the error span is in the code itself
+    pub fn new(code: &'s StyleCode<'s>) -> Result<Self, raffia::error::Error> {
+        let (parsed, syntax) = code.parsed();
+        let stylesheet = ParserBuilder::new(parsed)
+            .ignore_comments()
+            .syntax(syntax.into())
+            .build()
+            .parse()
+            .map_err(|e| raffia::error::Error {
+                span: code.original_span(&e.span),
+                ..e
+            })?;
+
         Ok(Self {
-            stylesheet: ParserBuilder::new(source)
-                .ignore_comments()
-                .syntax(syntax.into())
-                .build()
-                .parse()?,
-            syntax,
-            source,
+            syntax: code.syntax,
+            code,
+            stylesheet,
         })
     }
 
-    pub fn source(&self) -> &str {
-        self.source
+    /// The code as it is written
+    pub fn source(&self) -> &'s str {
+        &self.code.code
+    }
+
+    /// The code the spans of the syntax tree are in, see [StyleCode::original_span]
+    pub fn parsed(&self) -> &'s str {
+        self.code.parsed().0
+    }
+
+    /// Span in the code of the span in the syntax tree
+    pub fn original_span(&self, span: &Span) -> Span {
+        self.code.original_span(span)
+    }
+
+    /// All the selectors of the stylesheet by kind, gathered with a single walk through the tree
+    pub fn gather_selectors(&self) -> Selectors<'_, 's> {
+        let mut selectors = Selectors::default();
+        selectors.visit_statements(&self.stylesheet.statements, false);
+        selectors
     }
 
     pub fn selectors(&self) -> Vec<CompoundSelector<'s>> {
-        Self::selectors_of(self.stylesheet.statements.iter().cloned()).collect()
+        self.gather_selectors()
+            .compounds
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
     pub fn class_selectors(&self) -> Vec<ClassSelector<'s>> {
-        self.selectors_by(|c| c.as_class().cloned())
+        self.gather_selectors()
+            .classes
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
     pub fn id_selectors(&self) -> Vec<IdSelector<'s>> {
-        self.selectors_by(|c| c.as_id().cloned())
+        self.gather_selectors().ids.into_iter().cloned().collect()
     }
 
     pub fn type_selectors(&self) -> Vec<TypeSelector<'s>> {
-        self.selectors_by(|c| c.as_type().cloned())
+        self.gather_selectors().types.into_iter().cloned().collect()
     }
 
     pub fn nesting_selectors(&self) -> Vec<NestingSelector<'s>> {
-        self.selectors_by(|c| c.as_nesting().cloned())
+        self.gather_selectors()
+            .nestings
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
     /// special selector case searching for :g(whatever), :glob(whatever), :global(whatever)
     pub fn glob_modified_selectors(&self) -> Vec<PseudoClassSelector<'s>> {
-        self.selectors_by(|c| {
-            let cps = match c.as_pseudo_class() {
-                Some(s) => s.clone(),
-                _ => return None,
-            };
-
-            let ident = match cps.name.as_literal().map(|r| r.raw) {
-                Some(i) => i,
-                _ => return None,
-            };
-
-            if ident == "global" {
-                Some(cps)
-            } else {
-                None
-            }
-        })
+        self.gather_selectors().globs.into_iter().cloned().collect()
+    }
+
+    /// Whether the pseudo class is the `:global(whatever)` scoping opt-out
+    pub fn is_glob_modifier(cps: &PseudoClassSelector<'s>) -> bool {
+        cps.name.as_literal().is_some_and(|r| r.raw == "global")
     }
 
     /// Return collected [Vec] of owned values modulo `T`, filtered by `F`.
     ///
-    /// This is preferred way to apply custom filtering on selectors over
-    /// `selectors().iter().filter_map()`
-    /// as it does not allocate intermediate vector
+    /// Clones every simple selector, so prefer [StylesheetAdapter::gather_selectors]
+    /// when borrowed ones are enough
     pub fn selectors_by<T, F>(&self, form: F) -> Vec<T>
     where
         F: FnMut(SimpleSelector<'s>) -> Option<T>,
     {
-        Self::selectors_of(self.stylesheet.statements.iter().cloned())
-            .flat_map(|cs| cs.children)
+        self.gather_selectors()
+            .compounds
+            .into_iter()
+            .flat_map(|cs| cs.children.iter().cloned())
             .filter_map(form)
             .collect()
     }
 
-    fn selectors_of(
-        c: impl Iterator<Item = Statement<'s>>,
-    ) -> impl Iterator<Item = CompoundSelector<'s>> {
-        // Hello. Its Dan.
-        // Im not proud of this,
-        // but more effective implementation
-        // was SO unreadable and SO infected with lifetimes
-        // I suck at those things
-
-        // For everyone trying to make this MORE OPTIMAL:
-        // increment the hour-counter and post your name here:
-        // 4 - Danik
-        // 5 - Yiffy
-        // 8 - Danik
-        c.filter_map(|s| s.as_qualified_rule().cloned())
-            .flat_map(|q| {
-                let thissels = q
-                    .selector
-                    .selectors
-                    .iter()
-                    .flat_map(|sel| &sel.children)
-                    .filter_map(|sc| sc.as_compound_selector())
-                    .cloned();
-
-                let blockstmt = q.block.statements.iter().cloned();
-                Self::selectors_of(blockstmt)
-                    .chain(thissels)
-                    .collect::<Vec<_>>()
-            })
+    /// Statements living inside of the given one.
+    ///
+    /// Besides the qualified rule block, at-rule blocks are walked as well:
+    /// `@media`, `@supports`, `@layer`, `@container`, `@document`,
+    /// `@include ... { }` content blocks, sass control flow and so on,
+    /// so scoping does not depend on how deep the rule is wrapped
+    fn nested_statements<'a>(s: &'a Statement<'s>) -> impl Iterator<Item = &'a Statement<'s>> {
+        let blocks: Vec<&'a SimpleBlock<'s>> = match s {
+            Statement::QualifiedRule(q) => vec![&q.block],
+            Statement::AtRule(a) => a.block.iter().collect(),
+            Statement::UnknownSassAtRule(a) => a.block.iter().collect(),
+            Statement::SassIfAtRule(i) => std::iter::once(&i.if_clause.block)
+                .chain(i.else_if_clauses.iter().map(|c| &c.block))
+                .chain(i.else_clause.iter())
+                .collect(),
+            _ => vec![],
+        };
+
+        blocks.into_iter().flat_map(|b| &b.statements)
+    }
+}
+
+/// Selectors of the stylesheet by kind, borrowed from its syntax tree.
+///
+/// Gathered by [StylesheetAdapter::gather_selectors] in a single walk, so the ones needing several kinds
+/// (like [crate::scoper::HashedScope::new]) do not walk the tree over and over.
+///
+/// Nested rules come before the rule they are nested into, and pseudo-class arguments
+/// before the compound selector they belong to, so hashing the names stays stable
+#[derive(Default)]
+pub struct Selectors<'a, 's> {
+    pub compounds: Vec<&'a CompoundSelector<'s>>,
+    pub classes: Vec<&'a ClassSelector<'s>>,
+    pub ids: Vec<&'a IdSelector<'s>>,
+    pub types: Vec<&'a TypeSelector<'s>>,
+    pub nestings: Vec<&'a NestingSelector<'s>>,
+    /// `:global(whatever)` pseudo classes, which arguments are not walked
+    pub globs: Vec<&'a PseudoClassSelector<'s>>,
+    /// Every statement, outer ones first, along with the flag of being inside of `:global { }` block
+    pub statements: Vec<(&'a Statement<'s>, bool)>,
+}
+
+impl<'a, 's> Selectors<'a, 's> {
+    fn visit_statements(
+        &mut self,
+        statements: impl IntoIterator<Item = &'a Statement<'s>>,
+        global: bool,
+    ) {
+        for s in statements {
+            let global = global
+                || s.as_qualified_rule()
+                    .is_some_and(StylesheetAdapter::is_glob_block);
+            self.statements.push((s, global));
+            self.visit_statements(StylesheetAdapter::nested_statements(s), global);
+
+            if let Some(q) = s.as_qualified_rule() {
+                for complex in &q.selector.selectors {
+                    self.visit_complex(complex);
+                }
+            }
+        }
+    }
+
+    fn visit_complex(&mut self, complex: &'a ComplexSelector<'s>) {
+        for compound in complex
+            .children
+            .iter()
+            .filter_map(|c| c.as_compound_selector())
+        {
+            self.visit_compound(compound);
+        }
+    }
+
+    /// Compound selectors found in arguments of `:is()`, `:where()`, `:not()`, `:has()` and alike
+    /// go first, then the compound selector itself.
+    ///
+    /// `:global()` arguments are not walked, as it is the explicit opt-out from scoping
+    fn visit_compound(&mut self, compound: &'a CompoundSelector<'s>) {
+        for cps in compound.children.iter().filter_map(|c| c.as_pseudo_class()) {
+            if StylesheetAdapter::is_glob_modifier(cps) {
+                continue;
+            }
+
+            match cps.arg.as_ref().map(|a| &a.kind) {
+                Some(PseudoClassSelectorArgKind::SelectorList(list)) => {
+                    for complex in &list.selectors {
+                        self.visit_complex(complex);
+                    }
+                }
+                Some(PseudoClassSelectorArgKind::RelativeSelectorList(list)) => {
+                    for rel in &list.selectors {
+                        self.visit_complex(&rel.complex_selector);
+                    }
+                }
+                Some(PseudoClassSelectorArgKind::CompoundSelectorList(list)) => {
+                    for compound in &list.selectors {
+                        self.visit_compound(compound);
+                    }
+                }
+                Some(PseudoClassSelectorArgKind::CompoundSelector(c)) => self.visit_compound(c),
+                _ => {}
+            }
+        }
+
+        self.compounds.push(compound);
+        for simple in &compound.children {
+            match simple {
+                SimpleSelector::Class(c) => self.classes.push(c),
+                SimpleSelector::Id(i) => self.ids.push(i),
+                SimpleSelector::Type(t) => self.types.push(t),
+                SimpleSelector::Nesting(n) => self.nestings.push(n),
+                SimpleSelector::PseudoClass(cps) if StylesheetAdapter::is_glob_modifier(cps) => {
+                    self.globs.push(cps)
+                }
+                _ => {}
+            }
+        }
     }
 }
 
@@ -132,7 +286,7 @@ mod test {
 
     use crate::syntax::ostrta::OneSyntaxToRuleThemAll;
 
-    use super::StylesheetAdapter;
+    use super::{StyleCode, StylesheetAdapter};
 
     #[test]
     fn sels() {
@@ -186,7 +340,8 @@ div#id2
         let expect_tags = HashSet::from(["ul", "div", "span"]);
 
         //scss
-        let adp = StylesheetAdapter::new(source_scss, OneSyntaxToRuleThemAll::Scss).unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source_scss);
+        let adp = StylesheetAdapter::new(&style).unwrap();
 
         let classes = adp
             .class_selectors()
@@ -212,7 +367,8 @@ div#id2
         assert_eq!(expect_tags, tags);
 
         //sass
-        let adp = StylesheetAdapter::new(source_sass, OneSyntaxToRuleThemAll::Sass).unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Sass, source_sass);
+        let adp = StylesheetAdapter::new(&style).unwrap();
 
         let classes = adp
             .class_selectors()
@@ -238,13 +394,172 @@ div#id2
         assert_eq!(expect_tags, tags);
     }
 
+    #[test]
+    fn sels_in_at_rules() {
+        let source_scss = "
+@media (max-width: 600px) {
+  .card {
+    color: red;
+    @supports (display: grid) {
+      .grid {
+        display: grid;
+      }
+    }
+  }
+}
+@layer base {
+  #root {
+    color: red;
+  }
+}
+@container sidebar (min-width: 400px) {
+  .side {
+    color: red;
+  }
+}
+@document url(http://www.w3.org/) {
+  .doc {
+    color: red;
+  }
+}
+@include breakpoint(md) {
+  .bp {
+    color: red;
+  }
+}
+@if $dark {
+  .dark {
+    color: black;
+  }
+}
+@for $i from 1 through 2 {
+  .light {
+    color: white;
+  }
+}
+        ";
+        let source_sass = "
+@media (max-width: 600px)
+    .card
+        color: red
+        @supports (display: grid)
+            .grid
+                display: grid
+@layer base
+    #root
+        color: red
+@container sidebar (min-width: 400px)
+    .side
+        color: red
+@document url(http://www.w3.org/)
+    .doc
+        color: red
+@include breakpoint(md)
+    .bp
+        color: red
+@if $dark
+    .dark
+        color: black
+@for $i from 1 through 2
+    .light
+        color: white
+        ";
+        let expect_classes = HashSet::from(["card", "grid", "side", "doc", "bp", "dark", "light"]);
+        let expect_ids = HashSet::from(["root"]);
+
+        for (source, syntax) in [
+            (source_scss, OneSyntaxToRuleThemAll::Scss),
+            (source_sass, OneSyntaxToRuleThemAll::Sass),
+        ] {
+            let style = StyleCode::new(syntax, source);
+            let adp = StylesheetAdapter::new(&style).unwrap();
+
+            let classes = adp
+                .class_selectors()
+                .iter()
+                .filter_map(|c| c.name.as_literal().map(|l| l.raw))
+                .collect::<HashSet<_>>();
+
+            let ids = adp
+                .id_selectors()
+                .iter()
+                .filter_map(|i| i.name.as_literal().map(|l| l.raw))
+                .collect::<HashSet<_>>();
+
+            assert_eq!(expect_classes, classes);
+            assert_eq!(expect_ids, ids);
+        }
+    }
+
+    #[test]
+    fn sels_in_pseudo_args() {
+        let source = "
+.list:not(.active) {
+  color: red;
+  &:has(> .icon, #badge) {
+    color: red;
+  }
+}
+:is(.a, .b) :where(.c:not(.d)) {
+  color: red;
+}
+.e:not(:global(.f)) {
+  color: red;
+}
+        ";
+        let expect_classes = HashSet::from(["list", "active", "icon", "a", "b", "c", "d", "e"]);
+        let expect_ids = HashSet::from(["badge"]);
+
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let adp = StylesheetAdapter::new(&style).unwrap();
+
+        let classes = adp
+            .class_selectors()
+            .iter()
+            .filter_map(|c| c.name.as_literal().map(|l| l.raw))
+            .collect::<HashSet<_>>();
+
+        let ids = adp
+            .id_selectors()
+            .iter()
+            .filter_map(|i| i.name.as_literal().map(|l| l.raw))
+            .collect::<HashSet<_>>();
+
+        assert_eq!(expect_classes, classes);
+        assert_eq!(expect_ids, ids);
+        assert_eq!(adp.glob_modified_selectors().len(), 1);
+    }
+
+    #[test]
+    fn sels_single_pass_order() {
+        let source = ".a#i1 { .b { color: red; } &:hover { color: red; } } .c:not(.d) :global(.e) div { color: red; }";
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, source);
+        let adp = StylesheetAdapter::new(&style).unwrap();
+        let sels = adp.gather_selectors();
+
+        // nested rules and pseudo-class arguments go first, as hashing relies on the order
+        let classes = sels
+            .classes
+            .iter()
+            .filter_map(|c| c.name.as_literal().map(|l| l.raw))
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["b", "a", "d", "c"], classes);
+
+        assert_eq!(1, sels.ids.len());
+        assert_eq!(1, sels.types.len());
+        assert_eq!(1, sels.nestings.len());
+        assert_eq!(1, sels.globs.len());
+        assert_eq!(7, sels.compounds.len());
+    }
+
     #[test]
     fn rename_class() {
         let scss = ".cls1{color:red; .cls2{color:green;}}";
         let newname = "cls203-jg7ihgjhftyfhjh";
         let expect = ".cls1{color:red; .cls203-jg7ihgjhftyfhjh{color:green;}}";
 
-        let adp = StylesheetAdapter::new(scss, OneSyntaxToRuleThemAll::Scss).unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, scss);
+        let adp = StylesheetAdapter::new(&style).unwrap();
         let classes = adp.class_selectors();
         let target_class = classes[0].clone();
 
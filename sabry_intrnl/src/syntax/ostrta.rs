@@ -1,15 +1,14 @@
 /// Convenience unification entrypoint
 /// to make friends with all the different syntax-consumers
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub enum OneSyntaxToRuleThemAll {
     Sass,
+    #[default]
     Scss,
-}
-
-impl Default for OneSyntaxToRuleThemAll {
-    fn default() -> Self {
-        Self::Scss
-    }
+    /// Plain CSS, with the native nesting.
+    ///
+    /// Goes straight to lightningcss, bypassing grass
+    Css,
 }
 
 impl TryFrom<&str> for OneSyntaxToRuleThemAll {
@@ -18,6 +17,7 @@ impl TryFrom<&str> for OneSyntaxToRuleThemAll {
         match value {
             "sass" => Ok(Self::Sass),
             "scss" => Ok(Self::Scss),
+            "css" => Ok(Self::Css),
             _ => Err(()),
         }
     }
@@ -29,6 +29,7 @@ impl TryFrom<raffia::Syntax> for OneSyntaxToRuleThemAll {
         match value {
             raffia::Syntax::Sass => Ok(Self::Sass),
             raffia::Syntax::Scss => Ok(Self::Scss),
+            raffia::Syntax::Css => Ok(Self::Css),
             _ => Err(()),
         }
     }
@@ -40,6 +41,7 @@ impl TryFrom<grass::InputSyntax> for OneSyntaxToRuleThemAll {
         match value {
             grass::InputSyntax::Sass => Ok(Self::Sass),
             grass::InputSyntax::Scss => Ok(Self::Scss),
+            grass::InputSyntax::Css => Ok(Self::Css),
             _ => Err(()),
         }
     }
@@ -50,6 +52,7 @@ impl From<OneSyntaxToRuleThemAll> for raffia::Syntax {
         match value {
             OneSyntaxToRuleThemAll::Sass => Self::Sass,
             OneSyntaxToRuleThemAll::Scss => Self::Scss,
+            OneSyntaxToRuleThemAll::Css => Self::Css,
         }
     }
 }
@@ -59,6 +62,7 @@ impl From<OneSyntaxToRuleThemAll> for grass::InputSyntax {
         match value {
             OneSyntaxToRuleThemAll::Scss => Self::Scss,
             OneSyntaxToRuleThemAll::Sass => Self::Sass,
+            OneSyntaxToRuleThemAll::Css => Self::Css,
         }
     }
 }
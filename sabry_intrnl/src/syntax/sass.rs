@@ -0,0 +1,345 @@
+use raffia::Span;
+
+/// Indented SASS rewritten into SCSS, for raffia to parse.
+///
+/// Raffia tells a single dedentation however many blocks the line closes, so whatever follows
+/// gets nested into the wrong block, and rules like `@keyframes` or `@else` fail to parse.
+/// The rewrite only inserts braces and semicolons before the lines, closes the loud comments
+/// and expands `=mixin` and `+include` shorthands, so the offsets map back onto the SASS code
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SassAsScss {
+    code: String,
+    /// Rewrites, sorted by where they are in the SASS code
+    edits: Vec<Edit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Edit {
+    /// Offset in the SASS code
+    at: usize,
+    /// Length of the SASS code replaced
+    removed: usize,
+    /// Length of the SCSS code inserted instead, never less than the removed one
+    inserted: usize,
+}
+
+impl SassAsScss {
+    pub fn new(sass: &str) -> Self {
+        let mut this = Self {
+            code: String::with_capacity(sass.len() + sass.len() / 8),
+            edits: vec![],
+        };
+        let mut copied = 0;
+        let mut edit = |this: &mut Self, at: usize, removed: usize, inserted: &str| {
+            this.code.push_str(&sass[copied..at]);
+            this.code.push_str(inserted);
+            this.edits.push(Edit {
+                at,
+                removed,
+                inserted: inserted.len(),
+            });
+            copied = at + removed;
+        };
+
+        // indentation of the open block bodies
+        let mut blocks: Vec<usize> = vec![];
+        // statement to be ended by the next line: its indentation and whether it has to be a rule
+        let mut open: Option<(usize, bool)> = None;
+        let mut comment: Option<Comment> = None;
+        let mut continued = Continued::default();
+
+        for line in lines(sass) {
+            if line.content.is_empty() {
+                continue;
+            }
+
+            // comments go on as long as the lines are indented deeper
+            if let Some(c) = &mut comment {
+                if line.indent > c.indent {
+                    if c.loud {
+                        c.closed |= line.content.contains("*/");
+                        c.end = line.end;
+                    } else {
+                        edit(&mut this, line.start, 0, "//");
+                    }
+                    continue;
+                }
+                if c.loud && !c.closed {
+                    edit(&mut this, c.end, 0, " */");
+                }
+                comment = None;
+            }
+
+            if continued.goes_on() {
+                continued = continued.scan(line.content);
+                continue;
+            }
+
+            if let Some(c) = Comment::starting(&line) {
+                comment = Some(c);
+                continue;
+            }
+
+            let mut inserted = String::new();
+            if let Some((indent, rule)) = open.take() {
+                if line.indent > indent {
+                    inserted.push('{');
+                    blocks.push(line.indent);
+                } else {
+                    inserted.push_str(if rule { "{}" } else { ";" });
+                }
+            }
+            while blocks.last().is_some_and(|&b| b > line.indent) {
+                blocks.pop();
+                inserted.push('}');
+            }
+
+            let removed = match shorthand(line.content) {
+                Some(expanded) => {
+                    inserted.push_str(expanded);
+                    1
+                }
+                None => 0,
+            };
+            if !inserted.is_empty() {
+                edit(&mut this, line.start, removed, &inserted);
+            }
+
+            open = Some((line.indent, is_rule(line.content)));
+            continued = Continued::default().scan(line.content);
+        }
+
+        if let Some(c) = comment.filter(|c| c.loud && !c.closed) {
+            edit(&mut this, c.end, 0, " */");
+        }
+        // the last line may end with a comment
+        let mut inserted = String::from("\n");
+        if let Some((_, rule)) = open {
+            inserted.push_str(if rule { "{}" } else { ";" });
+        }
+        inserted.extend(blocks.iter().map(|_| '}'));
+        edit(&mut this, sass.len(), 0, &inserted);
+
+        this
+    }
+
+    /// The SCSS code
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Offset in the SASS code of the offset in the SCSS one.
+    ///
+    /// Inserted code maps onto the place it is inserted at
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let mut delta = 0;
+        for edit in &self.edits {
+            let start = edit.at + delta;
+            if offset < start {
+                break;
+            }
+            if offset < start + edit.inserted {
+                return edit.at;
+            }
+            delta += edit.inserted - edit.removed;
+        }
+        offset - delta
+    }
+
+    /// Span in the SASS code of the span in the SCSS one
+    pub fn original_span(&self, span: &Span) -> Span {
+        Span {
+            start: self.original_offset(span.start),
+            end: self.original_offset(span.end),
+        }
+    }
+}
+
+/// Non-blank line of the code
+struct Line<'s> {
+    indent: usize,
+    /// Offset where the content starts, after the indentation
+    start: usize,
+    /// Offset where the content ends, before the trailing whitespace
+    end: usize,
+    content: &'s str,
+}
+
+fn lines(code: &str) -> impl Iterator<Item = Line<'_>> {
+    let mut offset = 0;
+    code.split('\n').map(move |raw| {
+        let line_start = offset;
+        offset += raw.len() + 1;
+
+        let content = raw.trim_start_matches([' ', '\t']);
+        let indent = raw.len() - content.len();
+        let content = content.trim_end();
+        Line {
+            indent,
+            start: line_start + indent,
+            end: line_start + indent + content.len(),
+            content,
+        }
+    })
+}
+
+/// Comment taking the line, along with the lines indented deeper
+struct Comment {
+    indent: usize,
+    /// `/* */` one, which is closed by the rewrite unless the code does
+    loud: bool,
+    closed: bool,
+    /// Offset where the last line of the comment ends
+    end: usize,
+}
+
+impl Comment {
+    fn starting(line: &Line) -> Option<Self> {
+        let loud = if line.content.starts_with("//") {
+            false
+        } else if let Some(rest) = line.content.strip_prefix("/*") {
+            // code may go on right after the comment
+            if rest.contains("*/") && !rest.trim_end().ends_with("*/") {
+                return None;
+            }
+            true
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            indent: line.indent,
+            loud,
+            closed: line.content[2..].contains("*/"),
+            end: line.end,
+        })
+    }
+}
+
+/// Whether the statement goes on the next line: in the parentheses or after the comma
+#[derive(Default, Clone, Copy)]
+struct Continued {
+    nesting: usize,
+    comma: bool,
+}
+
+impl Continued {
+    fn goes_on(&self) -> bool {
+        self.nesting > 0 || self.comma
+    }
+
+    fn scan(mut self, content: &str) -> Self {
+        let mut quote = None;
+        let mut last = None;
+        let mut chars = content.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), '\\') => {
+                    chars.next();
+                }
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(' | '[') => self.nesting += 1,
+                (None, ')' | ']') => self.nesting = self.nesting.saturating_sub(1),
+                // `url(http://...)` is not a comment
+                (None, '/') if chars.peek() == Some(&'/') && last != Some(':') => break,
+                _ => {}
+            }
+            if !c.is_whitespace() {
+                last = Some(c);
+            }
+        }
+        self.comma = quote.is_none() && last == Some(',');
+        self
+    }
+}
+
+/// Expanded `=mixin` and `+include` shorthand, the first char of the line is replaced with
+fn shorthand(content: &str) -> Option<&'static str> {
+    let mut chars = content.chars();
+    let expanded = match chars.next()? {
+        '=' => "@mixin ",
+        '+' => "@include ",
+        _ => return None,
+    };
+    chars
+        .next()
+        .filter(|c| c.is_alphabetic() || *c == '_' || *c == '-')
+        .map(|_| expanded)
+}
+
+/// Whether the statement without nested ones is an empty rule rather than a declaration.
+///
+/// Rules like `a:hover` are told from declarations by the nested statements only,
+/// so those are taken for declarations, which they are as good as without any
+fn is_rule(content: &str) -> bool {
+    !content.starts_with(['$', '@', '=', '+', '-']) && !content.contains(':')
+}
+
+#[cfg(test)]
+mod test {
+    use super::SassAsScss;
+
+    #[test]
+    fn sass_rewritten_as_scss() {
+        let sass = "
+=dark
+  &-dark
+    color: black // not the white
+.card, .tile,
+.board
+  +dark
+  margin: (1px
+    + 2px)
+  /* loud
+     comment
+  @if $a == 1
+    .x
+      color: red
+  @else
+    .y
+  // silent
+    comment
+@keyframes fade
+  from
+    opacity: 0
+.z
+  color: red";
+        let expect_scss = "
+@mixin dark
+  {&-dark
+    {color: black // not the white
+;}}.card, .tile,
+.board
+  {@include dark
+  ;margin: (1px
+    + 2px)
+  /* loud
+     comment */
+  ;@if $a == 1
+    {.x
+      {color: red
+  ;}}@else
+    {.y
+  // silent
+    //comment
+{}}}@keyframes fade
+  {from
+    {opacity: 0
+;}}.z
+  {color: red
+;}";
+        let scss = SassAsScss::new(sass);
+        assert_eq!(expect_scss, scss.code());
+
+        for word in [".card", "dark", "&-dark", "margin", ".y", "from", ".z"] {
+            let original = scss.original_offset(expect_scss.rfind(word).unwrap());
+            assert!(sass[original..].starts_with(word));
+        }
+        // inserted code points where it is inserted
+        let inserted = expect_scss.find("@include").unwrap();
+        assert_eq!(sass.find("+dark").unwrap(), scss.original_offset(inserted));
+        assert_eq!(sass.len(), scss.original_offset(expect_scss.len()));
+    }
+}
@@ -1,25 +1,43 @@
-use std::{fmt::Debug, fs, path::PathBuf, str::FromStr};
+use std::{
+    fmt::Debug,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use proc_macro2::{Span, TokenStream};
+use itertools::Itertools;
+use proc_macro2::{Literal, Span, TokenStream};
 use quote::{quote, ToTokens, TokenStreamExt};
-use regex::Regex;
-use sabry_intrnl::syntax::ostrta::OneSyntaxToRuleThemAll;
+use sabry_intrnl::{
+    config::manifest,
+    diagnostic::{Diagnostic, LineCol},
+    syntax::ostrta::OneSyntaxToRuleThemAll,
+};
 use syn::{braced, parse::Parse, Ident, LitStr, Token};
+use unquoted::{unquoted, TokenSpans};
 
 pub mod scssy;
 pub mod styly;
+pub mod unquoted;
 pub mod usey;
 
 /// Macro input for arbitrary style code
 ///
 /// Also supports string literal token instead of braces,
-/// in which mode will look for relative file
+/// in which mode will look for relative file.
+/// The code within the braces is either quoted or written as tokens
 ///
 /// Does not work nice with rust-analyzer though
 #[derive(Clone, Debug)]
 pub struct ArbitraryStyleBlock {
     code: String,
     span: Span,
+    path: Option<PathBuf>,
+    /// Literal the code is taken from and its value, if the code is inline
+    lit: Option<(Literal, String)>,
+    /// Spans of the tokens the code is made of, if the code is unquoted
+    tokens: Option<TokenSpans>,
 }
 
 impl ArbitraryStyleBlock {
@@ -31,11 +49,32 @@ impl ArbitraryStyleBlock {
         &self.code
     }
 
+    /// Canonical path of the file the code is read from, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether the code is written as tokens rather than quoted
+    pub fn unquoted(&self) -> bool {
+        self.tokens.is_some()
+    }
+
+    /// Reject the code the syntax can not be written with: tokens do not keep the SASS indentation
+    pub fn for_syntax(self, syntax: ArbitraryStyleSyntax) -> syn::Result<Self> {
+        match syntax.0 {
+            OneSyntaxToRuleThemAll::Sass if self.unquoted() => Err(syn::Error::new(
+                self.span,
+                "SASS indented syntax can not be written unquoted, rust tokens do not keep the indentation.\n\ntip: use `{\"style\"}` or the scss syntax",
+            )),
+            _ => Ok(self),
+        }
+    }
+
     pub fn parse_syn(
         input: syn::parse::ParseStream,
         use_code_path_prefix: Option<PathBuf>,
     ) -> syn::Result<Self> {
-        let (code, span) = if let Ok(path_tok) = input.parse::<LitStr>() {
+        let (code, span, path, lit, tokens) = if let Ok(path_tok) = input.parse::<LitStr>() {
             let path = if let Some(prefix) = use_code_path_prefix {
                 let p = path_tok.value();
                 if let Some(pp) = p.strip_prefix("./") {
@@ -68,23 +107,209 @@ impl ArbitraryStyleBlock {
             };
             let code = String::from_utf8_lossy(&iofile).to_string();
 
-            (code, path_tok.span())
+            (code, path_tok.span(), Some(fullpath), None, None)
         } else {
             let s;
-            braced!(s in input);
-            if let Ok(stream) = s.parse::<LitStr>() {
-                let c = stream.value();
-                // shift the entire code for the first line ident
-                let ident_regex = Regex::new(r"\n\s{4}").expect("BUG: base ident regex at sabry_procmacro_impl/src/impls/mod.rs:: impl Parse for ArbitraryStyleBlock");
-                let c = ident_regex.replace_all(&c, "\n").to_string();
-                (c, stream.span())
-            } else {
-                return Err(syn::Error::new(s.span(), "Use \"\" within the braces to specify your SASS/SCSS. Unquoted style syntax is reserved for the future. Unquoted SCSS/SASS doesnt make sense though, as you won't benefit from it in rust file.\n\ntip: use `{\"style\"}` instead of `{style}`"));
+            let brace = braced!(s in input);
+            let quoted = s.fork();
+            match quoted.parse::<LitStr>() {
+                Ok(_) if quoted.is_empty() => {
+                    let stream = s.parse::<LitStr>()?;
+                    let value = stream.value();
+                    (
+                        dedent(&value),
+                        stream.span(),
+                        None,
+                        Some((stream.token(), value)),
+                        None,
+                    )
+                }
+                _ => {
+                    let (code, tokens) = unquoted(brace.span.open(), s.parse()?)?;
+                    (code, brace.span.join(), None, None, Some(tokens))
+                }
             }
         };
 
-        Ok(Self { code, span })
+        Ok(Self {
+            code,
+            span,
+            path,
+            lit,
+            tokens,
+        })
     }
+
+    /// Compile error of the diagnostic about the code, pointing at its span or location.
+    ///
+    /// Points right into the string literal where the toolchain allows literal subspans (nightly),
+    /// otherwise spans the whole block and renders the [Self::diagnose]d message
+    pub fn error_at(&self, diagnostic: Diagnostic) -> syn::Error {
+        let diagnostic = diagnostic.in_code(&self.code);
+        if let Some(span) = self.token_span(&diagnostic) {
+            return syn::Error::new(span, diagnostic.message);
+        }
+        match self.literal_subspan(&diagnostic) {
+            Some(span) => syn::Error::new(span, diagnostic.message),
+            None => syn::Error::new(self.span, self.diagnose(diagnostic, None)),
+        }
+    }
+
+    /// Resolve the diagnostic about the code against the code as it is written:
+    /// either the file it is read from, or the rust file with the literal, where the toolchain tells it.
+    ///
+    /// `file` is the rust file the literal is in, for the toolchains which do not tell it
+    pub fn diagnose(&self, diagnostic: Diagnostic, file: Option<&Path>) -> Diagnostic {
+        let diagnostic = diagnostic.in_code(&self.code);
+        if let Some(token) = self.token_span(&diagnostic) {
+            let start = token.start();
+            // line is 0 where the toolchain does not tell span locations
+            if start.line == 0 {
+                return diagnostic;
+            }
+            let mut diagnostic = Diagnostic {
+                location: None,
+                frame: None,
+                ..diagnostic
+            }
+            .at(LineCol {
+                line: start.line - 1,
+                column: start.column,
+            });
+            if let Some(file) = token.local_file().or(file.map(Path::to_owned)) {
+                diagnostic = diagnostic.named(manifest::crate_relative(&file).display());
+            }
+            return diagnostic.in_code(&self.code);
+        }
+        let (Some(span), Some((token, value))) = (diagnostic.span.clone(), &self.lit) else {
+            // the file is read as is, so the frame is already right
+            return match &self.path {
+                Some(p) => diagnostic.named(manifest::crate_relative(p).display()),
+                None => diagnostic,
+            };
+        };
+
+        let (span, prefix, exact) = in_literal(token, value, span);
+        let mut diagnostic = Diagnostic {
+            span: Some(span.clone()),
+            location: None,
+            frame: None,
+            ..diagnostic
+        };
+        let literal = token.span().start();
+        // line is 0 where the toolchain does not tell span locations
+        if exact && literal.line > 0 {
+            let location = LineCol::of_offset(value, span.start);
+            let column = match location.line {
+                0 => literal.column + prefix + location.column,
+                _ => location.column,
+            };
+            diagnostic = diagnostic.at(LineCol {
+                line: literal.line - 1 + location.line,
+                column,
+            });
+            if let Some(file) = token.span().local_file().or(file.map(Path::to_owned)) {
+                diagnostic = diagnostic.named(manifest::crate_relative(&file).display());
+            }
+        }
+
+        diagnostic.in_code(value)
+    }
+
+    fn token_span(&self, diagnostic: &Diagnostic) -> Option<Span> {
+        self.tokens.as_ref()?.span_of(diagnostic.span.clone()?)
+    }
+
+    fn literal_subspan(&self, diagnostic: &Diagnostic) -> Option<Span> {
+        let (token, value) = self.lit.as_ref()?;
+        let (span, prefix, exact) = in_literal(token, value, diagnostic.span.clone()?);
+        if !exact {
+            return None;
+        }
+
+        // empty span is still to be seen
+        let first = value[span.start..].chars().next().map_or(0, char::len_utf8);
+        let end = span.end.max(span.start + first);
+        token.subspan(prefix + span.start..prefix + end)
+    }
+}
+
+/// Span in the literal value of the span in the code, along with the length of the literal prefix.
+///
+/// Escapes make the value differ from the literal text, so the span is only exact without them
+fn in_literal(token: &Literal, value: &str, span: Range<usize>) -> (Range<usize>, usize, bool) {
+    let start = undedent(value, span.start);
+    let end = undedent(value, span.end).max(start);
+
+    let text = token.to_string();
+    let prefix = text.find('"').map_or(1, |q| q + 1);
+    let exact = text.get(prefix..prefix + value.len()) == Some(value);
+
+    (start..end, prefix, exact)
+}
+
+/// Inline code with the indentation common to its lines removed, whatever it is made of.
+///
+/// The first line follows the opening quote, so there is nothing to tell its indentation by:
+/// it is kept as is, and the rest of the lines are dedented by their own common indentation
+fn dedent(value: &str) -> String {
+    let indent = common_indent(value);
+    value
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| &line[dedented_by(line, indent, i)..])
+        .join("\n")
+}
+
+/// Indentation common to the non-blank lines after the first one, see [dedent]
+fn common_indent(value: &str) -> &str {
+    value
+        .split('\n')
+        .skip(1)
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start_matches([' ', '\t']).len()])
+        .reduce(|common, indent| &common[..common_prefix(common, indent)])
+        .unwrap_or_default()
+}
+
+/// How much of the line is removed by [dedent]: blank lines may be indented less
+fn dedented_by(line: &str, indent: &str, index: usize) -> usize {
+    match index {
+        0 => 0,
+        _ => common_prefix(line, indent),
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+/// Offset in the literal value of the given offset in the [dedent]ed code
+fn undedent(value: &str, offset: usize) -> usize {
+    let indent = common_indent(value);
+    let (mut dedented, mut shift) = (0, 0);
+    for (i, line) in value.split('\n').enumerate() {
+        let removed = dedented_by(line, indent, i);
+        let len = line.len() - removed;
+        shift += removed;
+        if offset <= dedented + len {
+            break;
+        }
+        dedented += len + 1;
+    }
+    offset + shift
+}
+
+/// Make the files compile-time dependencies of the crate, so it is recompiled when they change
+///
+/// Proc macros have no stable way to tell that, so the files are included into unnamed constants
+pub fn track_files<'p>(paths: impl IntoIterator<Item = &'p Path>) -> TokenStream {
+    let includes = paths.into_iter().map(|p| {
+        let path = p.to_string_lossy();
+        quote! {const _: &[u8] = include_bytes!(#path);}
+    });
+
+    quote! {#(#includes)*}
 }
 
 impl Parse for ArbitraryStyleBlock {
@@ -114,6 +339,7 @@ impl ToTokens for ArbitraryStyleSyntax {
         match self.0 {
             OneSyntaxToRuleThemAll::Sass => tokens.append_all(quote! {"sass"}),
             OneSyntaxToRuleThemAll::Scss => tokens.append_all(quote! {"scss"}),
+            OneSyntaxToRuleThemAll::Css => tokens.append_all(quote! {"css"}),
         }
     }
 }
@@ -141,7 +367,7 @@ impl Parse for ArbitraryStyleSyntax {
             Err(_) => Err(syn::Error::new(
                 ident.span(),
                 format!(
-                    "Available syntax are `sass` and `scss`, omit to use default {:?} syntax",
+                    "Available syntax are `sass`, `scss` and `css`, omit to use default {:?} syntax",
                     Self::default()
                 ),
             )),
@@ -200,4 +426,131 @@ mod test {
 
         assert_eq!(expect_code, block.code)
     }
+
+    #[test]
+    fn arbitrary_style_block_common_indent() {
+        use super::{undedent, ArbitraryStyleBlock};
+
+        // tabs, two spaces and a blank line indented less than the code
+        let code = "\n\t\t.a\n\t\t\tc: r\n\t\n\t\t.b\n\t\t\tc: w";
+        let expect_code = "\n.a\n\tc: r\n\n.b\n\tc: w";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(&format!("{{\"{code}\"}}")).unwrap();
+        assert_eq!(expect_code, block.code);
+        let offset = expect_code.find(".b").unwrap();
+        assert_eq!(code.find(".b").unwrap(), undedent(code, offset));
+
+        let code = "\n  .a\n    c: r";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(&format!("{{\"{code}\"}}")).unwrap();
+        assert_eq!("\n.a\n  c: r", block.code);
+
+        // the first line is right after the quote, the rest is indented along with the macro
+        let code = ".a {\n            c: r;\n        }\n        .b\n          c: w";
+        let expect_code = ".a {\n    c: r;\n}\n.b\n  c: w";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(&format!("{{\"{code}\"}}")).unwrap();
+        assert_eq!(expect_code, block.code);
+        let offset = expect_code.find(".b").unwrap();
+        assert_eq!(code.find(".b").unwrap(), undedent(code, offset));
+
+        let code = ".a {c: r;}";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(&format!("{{\"{code}\"}}")).unwrap();
+        assert_eq!(code, block.code);
+    }
+
+    #[test]
+    fn arbitrary_style_block_unquoted() {
+        use sabry_intrnl::{diagnostic::Diagnostic, syntax::ostrta::OneSyntaxToRuleThemAll};
+
+        use super::{ArbitraryStyleBlock, ArbitraryStyleSyntax};
+
+        let input = "{
+        #a.b {
+            c: 10px;
+            colr red;
+        }
+    }";
+        let expect_code = "
+#a.b {
+    c: 10px;
+    colr red;
+}";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(input).unwrap();
+        assert!(block.unquoted());
+        assert_eq!(expect_code, block.code);
+
+        let start = block.code().find("colr").unwrap();
+        let start = block
+            .error_at(Diagnostic::error("err").with_span(start..start + 4))
+            .span()
+            .start();
+        assert_eq!((4, 12), (start.line, start.column));
+
+        // string literal followed by the tokens is not the quoted code
+        let block = syn::parse_str::<ArbitraryStyleBlock>("{\"a\" {}}").unwrap();
+        assert_eq!("\"a\" {}", block.code);
+
+        let sass = ArbitraryStyleSyntax(OneSyntaxToRuleThemAll::Sass);
+        let block = syn::parse_str::<ArbitraryStyleBlock>(input).unwrap();
+        assert!(block.for_syntax(sass).is_err());
+        let block = syn::parse_str::<ArbitraryStyleBlock>("{\".a\"}").unwrap();
+        assert!(block.for_syntax(sass).is_ok());
+    }
+
+    #[test]
+    fn error_at_literal_position() {
+        use sabry_intrnl::{
+            diagnostic::{Diagnostic, LineCol},
+            scoper::ArbitraryScope,
+            syntax::{ostrta::OneSyntaxToRuleThemAll, StyleCode},
+        };
+
+        use super::ArbitraryStyleBlock;
+
+        let input = "{\"
+    .a {
+        color: red;
+    }
+    .b {
+        color: red;;;}}
+    }\"}";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(input).unwrap();
+        let style = StyleCode::new(OneSyntaxToRuleThemAll::Scss, block.code());
+        let Err(e) = ArbitraryScope::from_source(syn::parse_str("scope").unwrap(), &style) else {
+            panic!("invalid code is scoped")
+        };
+        let diagnostic = Diagnostic::from(&e);
+        let offset = diagnostic.span.clone().unwrap().start;
+        assert_eq!("}", &block.code()[offset..offset + 1]);
+
+        // literals parsed outside of proc macros support subspans
+        let start = block.error_at(diagnostic).span().start();
+        assert_eq!((6, 22), (start.line, start.column));
+        let start = block
+            .error_at(Diagnostic::error("err").at(LineCol::default()))
+            .span()
+            .start();
+        assert_eq!((1, 2), (start.line, start.column));
+    }
+
+    #[test]
+    fn error_at_escaped_literal() {
+        use sabry_intrnl::diagnostic::Diagnostic;
+
+        use super::ArbitraryStyleBlock;
+
+        let input = "{\"
+    .a {
+        content: \\\"a\\\";
+        colr red;
+    }\"}";
+        let block = syn::parse_str::<ArbitraryStyleBlock>(input).unwrap();
+        let start = block.code().find("colr").unwrap();
+        let message = block
+            .error_at(Diagnostic::error("err").with_span(start..start + 4))
+            .to_string();
+
+        assert_eq!(
+            "err\n --> 4:9\n  |\n4 |         colr red;\n  |         ^^^^",
+            message
+        );
+    }
 }
@@ -2,13 +2,13 @@ use std::path::PathBuf;
 
 use proc_macro2::TokenStream;
 use quote::quote;
-use sabry_intrnl::scoper::ArbitraryScope;
+use sabry_intrnl::{scoper::ArbitraryScope, syntax::StyleCode};
 use syn::{
     parse::{Parse, ParseStream},
     Ident,
 };
 
-use super::{ArbitraryStyleBlock, ArbitraryStyleSyntax};
+use super::{track_files, ArbitraryStyleBlock, ArbitraryStyleSyntax};
 
 /// Syntax:
 /// `#ident(:$syntax)? { $code }`
@@ -30,15 +30,17 @@ pub fn scssy_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
     };
 
     // quick raffia syntax check
-    match ArbitraryScope::from_source(syntax.0, ident.clone(), &code.code) {
+    match ArbitraryScope::from_source(ident.clone(), &StyleCode::new(syntax.0, code.code())) {
         Ok(_) => {}
         Err(e) => return syn::Error::new(code.span, format!("{e:?}")).into_compile_error(),
     }
 
+    let tracked = track_files(code.path());
     let sourcesass = code.to_string();
     let macro_doc = format!("Arbitrary {:?} code declared with `scssy!`. Pretty usable in tandem with `usey!` and `buildy` at build time", syntax);
 
     quote! {
+        #tracked
         #[doc = #macro_doc]
         #[macro_export]
         macro_rules! #ident {
@@ -65,7 +67,8 @@ impl MacroSyntax {
         let code = match source_path {
             Some(sp) => ArbitraryStyleBlock::parse_syn(input, Some(sp))?,
             None => input.parse::<ArbitraryStyleBlock>()?,
-        };
+        }
+        .for_syntax(syntax)?;
 
         Ok(Self {
             ident,
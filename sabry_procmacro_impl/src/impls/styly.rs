@@ -1,19 +1,32 @@
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
+use regex::Regex;
 use sabry_intrnl::{
     compiler::CompilerAdapter,
-    config::SabryConfig,
-    scoper::{apply_basic_rusty_member_gen_rules, ArbitraryScope, ScopedSelector},
+    config::{
+        manifest::{self, SCOPE_MODULES_ENV},
+        BehavHashCollision, BehavNamingConvention, SabryConfig,
+    },
+    diagnostic::Diagnostic,
+    scoper::{
+        mangle::MangleTable, member_name, origin::ScopeOrigin, registry::HashRegistry, rusty_ident,
+        unique_member_names, ArbitraryScope, ScopedSelector,
+    },
+    syntax::StyleCode,
 };
 use syn::{
     parse::{Parse, ParseStream},
     Ident, Token,
 };
 
-use super::{ArbitraryStyleBlock, ArbitraryStyleSyntax};
+use super::{track_files, ArbitraryStyleBlock, ArbitraryStyleSyntax};
 
 /// Syntax:
 /// `pub? #ident(:$syntax)? { $code } \#?`
@@ -33,7 +46,7 @@ use super::{ArbitraryStyleBlock, ArbitraryStyleSyntax};
 /// The machine-readable output may also be forced by use `machine_readable: true` arg on `parse_macro_syntax` function
 /// without modifying tokenstream
 pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> TokenStream {
-    let config = match SabryConfig::require() {
+    let mut config = match SabryConfig::require() {
         Ok(c) => c,
         Err(e) => {
             return syn::Error::new(
@@ -46,13 +59,72 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
             .to_compile_error()
         }
     };
-    let ms = match parse_macro_syntax(input, source_path) {
+
+    if let Err(e) = take_built_files(&mut config) {
+        return e.to_compile_error();
+    }
+
+    styly_with_config(&config, input, source_path)
+}
+
+/// Take the files `buildy` has written for the crate into the config, so the macro agrees with the bundle.
+///
+/// Files, which are told but can not be read, are errors, as names would silently differ from the bundled ones
+fn take_built_files(config: &mut SabryConfig) -> syn::Result<()> {
+    let unreadable = |what: &str, e: io::Error| {
+        syn::Error::new(
+            Span::call_site(),
+            format!(
+                "Could not read the {what} `buildy` has written: {e}. Try to rebuild the crate"
+            ),
+        )
+    };
+
+    if config.hash.mangle && config.hash.mangle_names.is_none() {
+        config.hash.mangle_names =
+            MangleTable::built().map_err(|e| unreadable("mangling table", e))?;
+    }
+    // collisions are neither resolved nor reported otherwise
+    if !matches!(config.hash.collision, BehavHashCollision::Ignore)
+        && config.hash.registry.is_none()
+    {
+        config.hash.registry = HashRegistry::built().map_err(|e| unreadable("hash registry", e))?;
+    }
+
+    Ok(())
+}
+
+/// [styly_macro_impl] with the given config rather than the one of the crate
+fn styly_with_config(
+    config: &SabryConfig,
+    input: TokenStream,
+    source_path: Option<PathBuf>,
+) -> TokenStream {
+    let mut ms = match parse_macro_syntax(input, source_path) {
         Ok(ms) => ms,
         Err(e) => return e.to_compile_error(),
     };
+    ms.origin = match macro_origin(ms.scope.span()) {
+        Ok(origin) => origin,
+        Err(e) => return e.to_compile_error(),
+    };
 
-    let scope = match ArbitraryScope::from_source(ms.syntax.0, ms.scope.clone(), &ms.code.code) {
-        Ok(s) => match s.hashed(&config.hash) {
+    let compiler = CompilerAdapter::new(config.clone());
+    let (syntax, code) = match compiler.scopable(ms.syntax.0, &ms.code.code) {
+        Ok(sc) => sc,
+        Err(e) => {
+            let diagnostic = Diagnostic::from(&e).context("Could not compile style to be scoped");
+            return match e.location() {
+                Some(_) => ms.code.error_at(diagnostic),
+                None => syn::Error::new(ms.code.span, diagnostic.summary()),
+            }
+            .to_compile_error();
+        }
+    };
+
+    let code = StyleCode::new(syntax, code);
+    let scope = match ArbitraryScope::from_source(ms.scope.clone(), &code) {
+        Ok(s) => match s.with_origin(ms.origin.clone()).hashed(&config.hash) {
             Ok(hs) => Ok(hs),
             Err(e) => Err(e),
         },
@@ -61,16 +133,53 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
     let scope = match scope {
         Ok(s) => s,
         Err(e) => {
+            let diagnostic = Diagnostic::from(&e).context("Could not generate scope");
+            // compiled code has nothing to do with the one written, so only the source is pointed at
+            return match code.code {
+                Cow::Borrowed(_) => ms.code.error_at(diagnostic),
+                Cow::Owned(_) => {
+                    syn::Error::new(ms.code.span, format!("Could not generate scope: {e:?}"))
+                }
+            }
+            .to_compile_error();
+        }
+    };
+
+    // the builder has already resolved collisions, if any. Yet the registry may tell about ones left
+    if !matches!(config.hash.collision, BehavHashCollision::Ignore) {
+        let scope_name = scope.original_scope.qualified_name();
+        if let Some(other) = config
+            .hash
+            .registry
+            .as_ref()
+            .and_then(|r| r.colliding(&scope_name, scope.hash.as_str()))
+        {
             return syn::Error::new(
-                Span::call_site(),
-                format!("Could not generate scope: {:?}", e),
+                ms.scope.span(),
+                format!("Scope '{scope_name}' has the same hash as '{other}'. Try to adjust config, increase hash size, or change the style code"),
             )
-            .to_compile_error()
+            .to_compile_error();
         }
-    };
+    }
 
     match ms.generator {
         ScopeGenerator::Module { public, constant } => {
+            // grass runs right here for const CSS and the compiled stage,
+            // so the modules it loads are dependencies as well
+            let modules = match constant || matches!(code.code, Cow::Owned(_)) {
+                true => used_modules(
+                    &ms.code.code,
+                    &manifest::crate_path(&config.sass.intermediate_dir),
+                ),
+                false => vec![],
+            };
+            let tracked = track_files(
+                ms.code
+                    .path()
+                    .into_iter()
+                    .chain(modules.iter().map(|m| m.as_path())),
+            );
+
             let scope_hash = scope.hash.as_str();
             let scope_ident = scope.original_scope.name.clone();
             let scope_wrapper_ident =
@@ -82,8 +191,19 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
                 quote! {}
             };
 
-            let nests = scope.original_scope.adapter().nesting_selectors();
-            let special_nesting_members = nests
+            let naming = &config.naming;
+            // lints are only allowed where the naming convention breaks them
+            let const_lint = match naming.convention {
+                BehavNamingConvention::Screaming => quote! {},
+                _ => quote! {#[allow(non_upper_case_globals)]},
+            };
+            let fn_lint = match naming.convention {
+                BehavNamingConvention::Snake => quote! {},
+                _ => quote! {#[allow(non_snake_case)]},
+            };
+
+            let nests = scope.original_scope.adapter().gather_selectors().nestings;
+            let nesting_suffixes = nests
                 .iter()
                 .filter_map(|ns| {
                     ns.suffix.clone().and_then(|s| {
@@ -96,44 +216,101 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
                     })
                 })
                 .unique()
-                .map(|s| {
-                    let ident = apply_basic_rusty_member_gen_rules(&s);
-                    let fnident = syn::parse_str::<Ident>(format!("_{ident}").as_str())
-                        .expect("BUG: invalid ident for nesting selector formed");
-                    let formatstr = format!("{{c}}{}", &s);
-                    let doc = format!("Nesting selector. Originated from '&{s}'");
-
-                    quote! {
-                        #[doc = #doc]
-                        pub fn #fnident(c: &str) -> String {format!(#formatstr)}
-                    }
-                });
-
-            let scope_members = scope
+                .collect::<Vec<_>>();
+            // class `.fade` and `@keyframes fade` are different members, while `.a` met twice is the same one
+            let selectors = scope
                 .hashed_selectors
                 .iter()
-                // not pretty sure about the best way to do this
-                .unique_by(|hs| hs.sel.as_arbitrary().ident.clone())
-                .filter_map(|hs| {
-                    hs.html_ident.clone().map(|html| {
-                        let name = match hs.sel {
-                            ScopedSelector::Class(_) => "class",
-                            ScopedSelector::Id(_) => "id",
-                            ScopedSelector::Tag(_) => "tagname",
-                            ScopedSelector::Glob { .. } => "glob",
-                        };
-
-                        (name, hs.sel.gen_rusty_ident(), html, hs.css_ident.clone())
-                    })
+                .unique_by(|hs| hs.sel.source())
+                .filter_map(|hs| Some((hs, hs.html_ident.clone()?, hs.sel.rusty_name(naming)?)))
+                .collect::<Vec<_>>();
+            let properties = scope
+                .hashed_selectors
+                .iter()
+                .filter_map(|hs| match &hs.sel {
+                    ScopedSelector::Property(p) => hs.html_ident.clone().map(|html| (p, html)),
+                    _ => None,
                 })
-                .map(|(name, ident, html, css)| {
-                    let docs = format!("'{:?}' {}. CSS selector '{}'", ident, name, css);
-                    quote! {
-                        #[doc = #docs]
-                        #[allow(non_upper_case_globals)]
-                        pub const #ident : &str = #html ;
-                    }
-                });
+                .unique_by(|(_, html)| html.clone())
+                .collect::<Vec<_>>();
+
+            // members share the value namespace, so names are told apart all together
+            let members = selectors
+                .iter()
+                .map(|(hs, _, name)| (hs.sel.source(), name.clone()))
+                .chain(properties.iter().map(|(p, _)| {
+                    (
+                        format!("style({})", p.ident),
+                        member_name(naming, "style", &p.ident),
+                    )
+                }))
+                .chain(
+                    nesting_suffixes
+                        .iter()
+                        .map(|s| (format!("&{s}"), format!("_{}", member_name(naming, "", s)))),
+                );
+            let names = match unique_member_names(members, naming.collision) {
+                Ok(names) => names,
+                Err(collisions) => {
+                    let collisions = collisions
+                        .iter()
+                        .map(|(name, sources)| {
+                            format!("`{name}` is made of `{}`", sources.join("`, `"))
+                        })
+                        .join("; ");
+                    return syn::Error::new(
+                        ms.scope.span(),
+                        format!("Scope members collide: {collisions}. Rename the selectors, or set `naming.collision = \"suffix\"` to tell them apart with `_2`, `_3` suffixes"),
+                    )
+                    .to_compile_error();
+                }
+            };
+            let ident_of = |source: &str| rusty_ident(&names[source]);
+
+            let special_nesting_members = nesting_suffixes.iter().map(|s| {
+                let fnident = ident_of(&format!("&{s}"));
+                let formatstr = format!("{{c}}{}", &s);
+                let doc = format!("Nesting selector. Originated from '&{s}'");
+
+                quote! {
+                    #[doc = #doc]
+                    #fn_lint
+                    pub fn #fnident(c: &str) -> String {format!(#formatstr)}
+                }
+            });
+
+            let scope_members = selectors.iter().map(|(hs, html, _)| {
+                let name = match hs.sel {
+                    ScopedSelector::Class(_) => "class",
+                    ScopedSelector::Id(_) => "id",
+                    ScopedSelector::Tag(_) => "tagname",
+                    ScopedSelector::Glob { .. } => "glob",
+                    ScopedSelector::Keyframes(_) => "keyframes",
+                    ScopedSelector::Container(_) => "container",
+                    ScopedSelector::CounterStyle(_) => "counter style",
+                    ScopedSelector::Property(_) => "property",
+                };
+                let ident = ident_of(&hs.sel.source());
+                let docs = format!("'{}' {}. CSS selector '{}'", ident, name, hs.css_ident);
+                quote! {
+                    #[doc = #docs]
+                    #const_lint
+                    pub const #ident : &str = #html ;
+                }
+            });
+
+            // inline style helpers for custom properties, so they may be set from rust code
+            let property_style_members = properties.iter().map(|(p, html)| {
+                let fnident = ident_of(&format!("style({})", p.ident));
+                let formatstr = format!("{html}:{{value}};");
+                let doc = format!("Inline style fragment, setting custom property '{html}'");
+
+                quote! {
+                    #[doc = #doc]
+                    #fn_lint
+                    pub fn #fnident(value: impl ::std::fmt::Display) -> String {format!(#formatstr)}
+                }
+            });
 
             let mod_docs = format!(
                 "'{}' style scope. The wrapper class for scoped tagnames is {}",
@@ -142,8 +319,7 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
             let wrp_docs = format!("wrapper class for '{}' scope. If you have any tagname selectors - they should live as children of element with this class applied.", &scope_ident);
 
             if constant {
-                let compiler = CompilerAdapter::new(config.clone());
-                let css = match compiler.compile_module(ms.syntax.0, &scope.hashed_code) {
+                let css = match compiler.compile_scoped(syntax, &scope.hashed_code) {
                     Ok(c) => c,
                     Err(e) => {
                         return syn::Error::new(
@@ -167,7 +343,9 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
                     #scope_vis const #scope_wrapper_ident : &str = #scope_hash ;
                     #[doc = #mod_docs]
                     #scope_vis mod #scope_ident {
+                        #tracked
                         #(#scope_members)*
+                        #(#property_style_members)*
                         #(#special_nesting_members)*
                     }
                 }
@@ -177,7 +355,9 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
                     #scope_vis const #scope_wrapper_ident : &str = #scope_hash ;
                     #[doc = #mod_docs]
                     #scope_vis mod #scope_ident {
+                        #tracked
                         #(#scope_members)*
+                        #(#property_style_members)*
                         #(#special_nesting_members)*
                     }
                 }
@@ -189,6 +369,85 @@ pub fn styly_macro_impl(input: TokenStream, source_path: Option<PathBuf>) -> Tok
     }
 }
 
+/// Origin of the scope the macro is called for, taken from the span of the scope ident.
+///
+/// The ident comes from the tokens of the caller, so a `macro_rules!` wrapper gets the file it is called in,
+/// like `buildy` does, rather than the one it is defined in.
+///
+/// The file is known to stable rust since 1.88. Module path is used as `buildy` tells with [SCOPE_MODULES_ENV],
+/// so hashes match the bundled ones. Without `buildy` the module path is used whenever the file is known
+fn macro_origin(scope: Span) -> syn::Result<ScopeOrigin> {
+    let file = scope.local_file();
+
+    match (env::var(SCOPE_MODULES_ENV).as_deref(), file) {
+        (Ok("0"), _) => Ok(ScopeOrigin::new(None)),
+        (Ok(_), None) => Err(syn::Error::new(
+            scope,
+            "`buildy` qualifies scopes with module path, but this macro is not told its source file by the toolchain. Scope names would not match the bundled ones",
+        )),
+        (_, file) => Ok(ScopeOrigin::new(file.as_deref())),
+    }
+}
+
+/// Files of the modules, which the code `@use`s, `@forward`s or `@import`s from the given directory,
+/// along with the ones those modules load in turn.
+///
+/// Modules load others relative to themselves first, as sass does
+fn used_modules(code: &str, dir: &Path) -> Vec<PathBuf> {
+    let mut modules = loaded_modules(code, &[dir]);
+
+    let mut next = 0;
+    while let Some(module) = modules.get(next).cloned() {
+        next += 1;
+        let Ok(code) = fs::read_to_string(&module) else {
+            continue;
+        };
+        let own_dir = module.parent().unwrap_or(dir);
+        for loaded in loaded_modules(&code, &[own_dir, dir]) {
+            if !modules.contains(&loaded) {
+                modules.push(loaded);
+            }
+        }
+    }
+
+    modules
+}
+
+/// Files of the modules the code loads directly, the first directory to have one wins.
+///
+/// Names are resolved the way sass does: as is, with extension, and as partials
+fn loaded_modules(code: &str, dirs: &[&Path]) -> Vec<PathBuf> {
+    let use_regex = Regex::new(r#"@(?:use|forward|import)\s+["']([^"']+)["']"#)
+        .expect("BUG: can not build module use regex");
+
+    use_regex
+        .captures_iter(code)
+        .filter_map(|c| {
+            let name = Path::new(c.get(1)?.as_str());
+            let partial = name
+                .file_name()
+                .map(|f| name.with_file_name(format!("_{}", f.to_string_lossy())))?;
+
+            let candidates = dirs
+                .iter()
+                .flat_map(|dir| [dir.join(name), dir.join(&partial)])
+                .flat_map(|p| {
+                    ["", "scss", "sass", "css"].map(|ext| match ext {
+                        "" => p.clone(),
+                        ext => p.with_extension(ext),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            candidates
+                .into_iter()
+                .find(|p| p.is_file())
+                .and_then(|p| p.canonicalize().ok())
+        })
+        .unique()
+        .collect()
+}
+
 // reusable function, that does not return tokenstream for machine-processing at build time
 pub fn parse_macro_syntax(
     input: TokenStream,
@@ -229,6 +488,10 @@ pub struct MacroSyntax {
     pub syntax: ArbitraryStyleSyntax,
     /// Style code, either from rust or read from file
     pub code: ArbitraryStyleBlock,
+    /// Where the macro is called. Crate only, unless the caller knows the file
+    pub origin: ScopeOrigin,
+    /// Rust source file the macro is called in, if the caller knows it
+    pub file: Option<PathBuf>,
 }
 
 impl MacroSyntax {
@@ -243,7 +506,8 @@ impl MacroSyntax {
             ArbitraryStyleBlock::parse_syn(input, source_path)
         } else {
             input.parse::<ArbitraryStyleBlock>()
-        }?;
+        }?
+        .for_syntax(syntax)?;
 
         if input.parse::<Token![#]>().is_ok() {
             generator = ScopeGenerator::MachineReadable
@@ -254,6 +518,8 @@ impl MacroSyntax {
             scope,
             syntax,
             code,
+            origin: ScopeOrigin::new(None),
+            file: None,
         })
     }
 }
@@ -263,3 +529,97 @@ impl Parse for MacroSyntax {
         Self::parse_syn(input, None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use proc_macro2::TokenStream;
+    use sabry_intrnl::config::{BehavNameCollision, BehavScopeStage, SabryConfig};
+
+    use super::{styly_with_config, used_modules};
+
+    #[test]
+    fn member_collision_error() {
+        let input = r#"scope {".self{color:red;} .self_{color:red;} .foo-bar{color:red;}"}"#
+            .parse::<TokenStream>()
+            .unwrap();
+
+        let mut config = SabryConfig::default();
+        let suffixed = styly_with_config(&config, input.clone(), None).to_string();
+        assert!(suffixed.contains("self__2"));
+        assert!(!suffixed.contains("compile_error"));
+
+        config.naming.collision = BehavNameCollision::Error;
+        let collided = styly_with_config(&config, input, None).to_string();
+        assert!(collided.contains("compile_error"));
+        assert!(collided.contains("Scope members collide: `self_` is made of `.self`, `.self_`"));
+    }
+
+    #[test]
+    fn used_modules_resolved() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in [
+            "_theme.sass",
+            "plain.css",
+            "nested/_shade.scss",
+            "_shade.scss",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(dir.join("mixins.scss"), r#"@forward "nested/colors";"#).unwrap();
+        // relative to the module itself first
+        fs::write(
+            dir.join("nested/_colors.scss"),
+            r#"@use "shade"; @use "theme";"#,
+        )
+        .unwrap();
+
+        let code = r#"
+            @use "mixins";
+            @use 'theme' as t;
+            @import "plain.css";
+            @forward "missing";
+            @use "sass:math";
+        "#;
+        let dir = dir.canonicalize().unwrap();
+        let names = used_modules(code, &dir)
+            .iter()
+            .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "mixins.scss",
+                "_theme.sass",
+                "plain.css",
+                "nested/_colors.scss",
+                "nested/_shade.scss"
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn compiled_stage_tracks_modules() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("_theme.scss"), "$accent: red;").unwrap();
+
+        let input = r#"scope {"@use 'theme'; .btn{color:theme.$accent;}"}"#
+            .parse::<TokenStream>()
+            .unwrap();
+        let mut config = SabryConfig::default();
+        config.sass.intermediate_dir = dir.to_string_lossy().to_string();
+
+        let source = styly_with_config(&config, input.clone(), None).to_string();
+        assert!(!source.contains("_theme.scss"));
+
+        config.hash.stage = BehavScopeStage::Compiled;
+        let compiled = styly_with_config(&config, input, None).to_string();
+        assert!(!compiled.contains("compile_error"));
+        assert!(compiled.contains("_theme.scss"));
+    }
+}
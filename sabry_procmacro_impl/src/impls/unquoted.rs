@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use proc_macro2::{Delimiter, LineColumn, Span, TokenStream, TokenTree};
+
+/// Style code written as rust tokens rather than in the string literal, along with the token spans.
+///
+/// Whitespace between the tokens is taken from where they are. It can not be guessed right:
+/// `.a.b` and `.a .b` are the same tokens. So the code is an error, unless the toolchain tells the token positions
+///
+/// `open` is the span of the brace the tokens follow
+pub fn unquoted(open: Span, stream: TokenStream) -> syn::Result<(String, TokenSpans)> {
+    let mut pieces = vec![];
+    flatten(stream, &mut pieces)?;
+
+    // the code is dedented by the indentation of the least indented line
+    let mut prev_span = open;
+    let mut base = None::<usize>;
+    for piece in &pieces {
+        if !located(prev_span, piece.span) {
+            return Err(syn::Error::new(
+                piece.span,
+                "Unquoted code needs rust 1.88+ to tell where its tokens are, quote it instead",
+            ));
+        }
+
+        let start = piece.span.start();
+        if start.line > prev_span.end().line {
+            base = Some(base.map_or(start.column, |b| b.min(start.column)));
+        }
+        prev_span = piece.span;
+    }
+    let base = base.unwrap_or_default();
+
+    let mut code = String::new();
+    let mut spans = vec![];
+    let mut prev_span = open;
+    for piece in &pieces {
+        let (start, prev_end) = (piece.span.start(), prev_span.end());
+        if start.line > prev_end.line {
+            code.push_str(&"\n".repeat(start.line - prev_end.line));
+            code.push_str(&" ".repeat(start.column.saturating_sub(base)));
+        } else {
+            code.push_str(&" ".repeat(start.column - prev_end.column));
+        }
+
+        spans.push((code.len()..code.len() + piece.text.len(), piece.span));
+        code.push_str(&piece.text);
+        prev_span = piece.span;
+    }
+
+    Ok((code, TokenSpans(spans)))
+}
+
+/// Spans of the tokens the code is made of, along with where they are in the code
+#[derive(Clone, Debug)]
+pub struct TokenSpans(Vec<(Range<usize>, Span)>);
+
+impl TokenSpans {
+    /// Span of the tokens the span in the code covers.
+    ///
+    /// Spans are only joined where the toolchain allows it (nightly), otherwise it is the first token
+    pub fn span_of(&self, span: Range<usize>) -> Option<Span> {
+        let first = self
+            .0
+            .iter()
+            .find(|(r, _)| r.end > span.start)
+            .or(self.0.last())?;
+        let last = self
+            .0
+            .iter()
+            .rev()
+            .find(|(r, _)| r.start < span.end && r.start >= first.0.start)
+            .unwrap_or(first);
+
+        Some(first.1.join(last.1).unwrap_or(first.1))
+    }
+}
+
+/// Text a token turns into
+struct Piece {
+    text: String,
+    span: Span,
+}
+
+fn flatten(stream: TokenStream, pieces: &mut Vec<Piece>) -> syn::Result<()> {
+    let mut tokens = stream.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let (text, span) = match token {
+            TokenTree::Group(g) => {
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    // groups of `macro_rules!` fragments
+                    Delimiter::None => {
+                        flatten(g.stream(), pieces)?;
+                        continue;
+                    }
+                };
+                pieces.push(Piece {
+                    text: open.into(),
+                    span: g.span_open(),
+                });
+                flatten(g.stream(), pieces)?;
+                (close.into(), g.span_close())
+            }
+            TokenTree::Ident(i) => {
+                let text = i.to_string();
+                let text = text.strip_prefix("r#").map(String::from).unwrap_or(text);
+                (text, i.span())
+            }
+            TokenTree::Punct(p) => {
+                // doc comments are turned into `#[doc = "..."]` by the tokenizer
+                if p.as_char() == '#' && tokens.peek().is_some_and(is_doc) {
+                    tokens.next();
+                    continue;
+                }
+                if p.as_char() == '\'' {
+                    return Err(syn::Error::new(
+                        p.span(),
+                        "Single quoted strings can not be written unquoted, use \"\" instead",
+                    ));
+                }
+                (p.as_char().to_string(), p.span())
+            }
+            TokenTree::Literal(l) => {
+                let text = l.to_string();
+                if text.starts_with(['r', 'b', 'c']) {
+                    return Err(syn::Error::new(
+                        l.span(),
+                        "Raw, byte and C string literals can not be written unquoted, use plain \"\" strings instead",
+                    ));
+                }
+                (text, l.span())
+            }
+        };
+        pieces.push(Piece { text, span });
+    }
+
+    Ok(())
+}
+
+fn is_doc(token: &TokenTree) -> bool {
+    match token {
+        TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket => g
+            .stream()
+            .into_iter()
+            .next()
+            .is_some_and(|t| matches!(t, TokenTree::Ident(i) if i == "doc")),
+        _ => false,
+    }
+}
+
+/// Whether the token positions are known, and the token is after the previous one.
+///
+/// Line is 0 where the toolchain does not tell span locations,
+/// and tokens made by macros share empty spans
+fn located(prev: Span, cur: Span) -> bool {
+    let at = |l: LineColumn| (l.line, l.column);
+    let known = |s: Span| s.start().line > 0 && at(s.start()) < at(s.end());
+    known(prev) && known(cur) && at(cur.start()) >= at(prev.end())
+}
+
+#[cfg(test)]
+mod test {
+    use proc_macro2::{Span, TokenStream, TokenTree};
+
+    use super::unquoted;
+
+    /// Span of the opening brace and the tokens within
+    fn braced(input: &str) -> (Span, TokenStream) {
+        match input.parse::<TokenStream>().unwrap().into_iter().next() {
+            Some(TokenTree::Group(g)) => (g.span_open(), g.stream()),
+            _ => panic!("not braced"),
+        }
+    }
+
+    #[test]
+    fn unquoted_with_locations() {
+        let input = "{
+        @use \"mixins\";
+        #main.card, .card > .title-text {
+            color: #fff;
+            margin: -10px 1.5rem;
+            width: calc(100% - $gap);
+            &:hover { color: rgb(1, 2, 3) !important; }
+            /// a comment
+            --accent-color: red;
+        }
+    }";
+        let expect_code = "
+@use \"mixins\";
+#main.card, .card > .title-text {
+    color: #fff;
+    margin: -10px 1.5rem;
+    width: calc(100% - $gap);
+    &:hover { color: rgb(1, 2, 3) !important; }
+
+    --accent-color: red;
+}";
+        let (open, stream) = braced(input);
+        let (code, spans) = unquoted(open, stream).unwrap();
+        assert_eq!(expect_code, code);
+
+        // span of the token where the code is
+        let title = code.find("title").unwrap();
+        let span = spans.span_of(title..title + 5).unwrap();
+        let column = input.lines().nth(2).unwrap().find("title").unwrap();
+        assert_eq!((3, column), (span.start().line, span.start().column));
+    }
+
+    #[test]
+    fn unquoted_without_locations() {
+        let input = "#main.card { margin: -10px 5%; }";
+
+        // spans shared by all the tokens, like ones made by `quote!`
+        let stream = input
+            .parse::<TokenStream>()
+            .unwrap()
+            .into_iter()
+            .map(|mut t| {
+                respan(&mut t);
+                t
+            })
+            .collect::<TokenStream>();
+        assert!(unquoted(Span::call_site(), stream).is_err());
+    }
+
+    fn respan(token: &mut TokenTree) {
+        use proc_macro2::Group;
+
+        if let TokenTree::Group(g) = token {
+            let stream = g
+                .stream()
+                .into_iter()
+                .map(|mut t| {
+                    respan(&mut t);
+                    t
+                })
+                .collect();
+            *g = Group::new(g.delimiter(), stream);
+        }
+        token.set_span(Span::call_site());
+    }
+
+    #[test]
+    fn unquoted_unrepresentable() {
+        for input in ["a { content: r\"x\"; }", "a { content: 'ab; }"] {
+            let Ok(stream) = input.parse::<TokenStream>() else {
+                continue;
+            };
+            assert!(unquoted(Span::call_site(), stream).is_err());
+        }
+    }
+}
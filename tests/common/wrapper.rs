@@ -0,0 +1,6 @@
+/// `styly!` wrapper, defined in another file than the one it is called in
+macro_rules! wrapped_styly {
+    ($($t:tt)*) => {
+        sabry::styly!($($t)*);
+    };
+}
@@ -67,13 +67,27 @@ pub mod scss {
 @forward 'mixins_a';
 @forward 'mixins_c';
 "});
+    // unquoted code needs token positions, which are known since rust 1.88
+    #[rustversion::since(1.88)]
+    scssy!(module_unquoted_scss:scss {
+        @forward "mixins_a";
+        @forward "mixins_c";
+    });
 
     pub(crate) use mixins_inline_scss as mixins_inline_c;
     pub(crate) use mixins_scss as mixins_c;
     pub(crate) use module_inline_scss as module_inline_c;
     pub(crate) use module_scss as module_c;
+    #[rustversion::since(1.88)]
+    pub(crate) use module_unquoted_scss as module_unquoted_c;
 }
 
+#[macro_use]
+#[path = "common/wrapper.rs"]
+mod wrapper;
+
+wrapped_styly!(wrapped_scss:scss "tests/assets/global.scss");
+
 #[test]
 fn ensure_inline_matches_file() {
     assert_eq!(sass::mixins_a!(), sass::mixins_inline_a!());
@@ -83,6 +97,15 @@ fn ensure_inline_matches_file() {
     assert_eq!(scss::module_c!(), scss::module_inline_c!());
 }
 
+#[rustversion::since(1.88)]
+#[test]
+fn unquoted_matches_quoted() {
+    assert_eq!(
+        "\n@forward \"mixins_a\";\n@forward \"mixins_c\";",
+        scss::module_unquoted_c!()
+    );
+}
+
 #[test]
 fn syntax_contract() {
     assert_eq!(scss::mixins_inline_c!(syntax), "scss");
@@ -150,6 +173,10 @@ mod build_tests {
 
     #[test]
     fn compilation_with_buildy() {
+        // leftovers of another run, like cached CSS of another scoping flavour, must not sneak in
+        let _ = fs::remove_dir_all("tests/sabry_output");
+        let _ = fs::remove_dir_all("tests/sabry_intermediate");
+
         let mut builder = gen_builder();
         builder
             .build(usey!(
@@ -164,7 +191,12 @@ mod build_tests {
             let generated = fs::read_to_string(path).unwrap();
 
             let mut settings = Settings::clone_current();
-            settings.set_snapshot_path("sabry_output_snapshots");
+            // tags are scoped differently with `lepty-scoping`
+            settings.set_snapshot_path(if cfg!(feature = "lepty-scoping") {
+                "sabry_output_snapshots_lepty"
+            } else {
+                "sabry_output_snapshots"
+            });
             settings.set_prepend_module_to_snapshot(false);
             settings.set_omit_expression(true);
             settings.remove_info();
@@ -172,5 +204,71 @@ mod build_tests {
                 insta::assert_snapshot!(generated);
             });
         });
+
+        let _ = fs::remove_dir_all("tests/sabry_output");
+        let _ = fs::remove_dir_all("tests/sabry_intermediate");
+    }
+
+    #[test]
+    fn wrapper_from_another_file() {
+        // the very config `styly!` takes, so the hashes are comparable
+        let mut config = SabryConfig::require().unwrap();
+        config.css.bundle = Some("tests/sabry_output_wrapped/bundle.css".into());
+        config.sass.intermediate_dir = "tests/sabry_intermediate_wrapped".into();
+        config.sass.scanroot = "tests".into();
+        config.sass.styly_wrappers = Some(vec!["wrapped_styly".into()]);
+
+        SabryBuilder::new(config)
+            .build(usey!(
+                super::sass::mixins_a!(),
+                super::sass::module_a!(),
+                super::scss::mixins_c!(),
+                super::scss::module_c!()
+            ))
+            .unwrap();
+        let bundle = fs::read_to_string("tests/sabry_output_wrapped/bundle.css").unwrap();
+
+        let _ = fs::remove_dir_all("tests/sabry_output_wrapped");
+        let _ = fs::remove_dir_all("tests/sabry_intermediate_wrapped");
+
+        // the wrapper is defined in another file, yet both take the scope for the one it is called in
+        assert!(bundle.contains(&format!(".{}.p1", super::WRAPPED_SCSS)));
+    }
+
+    #[test]
+    fn compilation_with_sourcemaps() {
+        let mut config = gen_config();
+        config.css.bundle = Some("tests/sabry_output_mapped/bundle.css".into());
+        config.css.scopes = Some("tests/sabry_output_mapped/scopes".into());
+        config.css.sourcemap = true;
+        config.sass.intermediate_dir = "tests/sabry_intermediate_mapped".into();
+
+        let mut builder = SabryBuilder::new(config);
+        builder
+            .build(usey!(
+                super::sass::mixins_a!(),
+                super::sass::module_a!(),
+                super::scss::mixins_c!(),
+                super::scss::module_c!()
+            ))
+            .unwrap();
+
+        let bundle = fs::read_to_string("tests/sabry_output_mapped/bundle.css").unwrap();
+        let map = fs::read_to_string("tests/sabry_output_mapped/bundle.css.map").unwrap();
+        let chunk_map = fs::read_dir("tests/sabry_output_mapped/scopes")
+            .unwrap()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .find(|p| p.to_string_lossy().ends_with("global_scss.css.map"))
+            .map(|p| fs::read_to_string(p).unwrap())
+            .unwrap();
+
+        let _ = fs::remove_dir_all("tests/sabry_output_mapped");
+        let _ = fs::remove_dir_all("tests/sabry_intermediate_mapped");
+
+        assert!(bundle.ends_with("/*# sourceMappingURL=bundle.css.map */\n"));
+        assert!(map.contains(r#""sourceRoot":"../../""#));
+        assert!(map.contains("tests/assets/global.scss"));
+        assert!(map.contains("tests/assets/sole-code.sass"));
+        assert!(chunk_map.contains(r#""sources":["tests/assets/global.scss"]"#));
     }
 }